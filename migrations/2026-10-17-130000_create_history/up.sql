@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE history (
+    `id` INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    `page_id` INTEGER NOT NULL,
+    `source_id` INTEGER NOT NULL,
+    `action` INTEGER NOT NULL,
+    `created` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (`page_id`) REFERENCES pages (`id`) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (`source_id`) REFERENCES sources (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+);
+CREATE INDEX history_created ON history (`created`);
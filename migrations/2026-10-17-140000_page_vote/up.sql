@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE pages ADD COLUMN vote INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE tags (
+    `id` INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    `name` TEXT NOT NULL UNIQUE
+);
+CREATE TABLE source_tags (
+    `source_id` INTEGER NOT NULL,
+    `tag_id` INTEGER NOT NULL,
+    PRIMARY KEY (`source_id`, `tag_id`),
+    FOREIGN KEY (`source_id`) REFERENCES sources (`id`) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (`tag_id`) REFERENCES tags (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+);
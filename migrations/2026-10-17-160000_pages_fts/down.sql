@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER IF EXISTS pages_fts_update;
+DROP TRIGGER IF EXISTS pages_fts_delete;
+DROP TRIGGER IF EXISTS pages_fts_insert;
+DROP TABLE IF EXISTS pages_fts;
@@ -0,0 +1,32 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER pages_fts_update;
+DROP TRIGGER pages_fts_delete;
+DROP TRIGGER pages_fts_insert;
+DROP TABLE pages_fts;
+CREATE VIRTUAL TABLE pages_fts USING fts5(
+    title,
+    url,
+    content='pages',
+    content_rowid='id'
+);
+INSERT INTO pages_fts(pages_fts) VALUES ('rebuild');
+
+CREATE TRIGGER pages_fts_insert AFTER INSERT ON pages BEGIN
+    INSERT INTO pages_fts (rowid, title, url) VALUES (new.id, new.title, new.url);
+END;
+CREATE TRIGGER pages_fts_delete AFTER DELETE ON pages BEGIN
+    INSERT INTO pages_fts (pages_fts, rowid, title, url)
+    VALUES ('delete', old.id, old.title, old.url);
+END;
+CREATE TRIGGER pages_fts_update AFTER UPDATE OF title, url ON pages BEGIN
+    INSERT INTO pages_fts (pages_fts, rowid, title, url)
+    VALUES ('delete', old.id, old.title, old.url);
+    INSERT INTO pages_fts (rowid, title, url) VALUES (new.id, new.title, new.url);
+END;
+
+ALTER TABLE pages DROP COLUMN updated;
+ALTER TABLE pages DROP COLUMN media;
+ALTER TABLE pages DROP COLUMN categories;
+ALTER TABLE pages DROP COLUMN authors;
+ALTER TABLE pages DROP COLUMN content;
+ALTER TABLE pages DROP COLUMN summary;
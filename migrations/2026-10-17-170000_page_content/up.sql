@@ -0,0 +1,37 @@
+-- Your SQL goes here
+ALTER TABLE pages ADD COLUMN summary TEXT NULL DEFAULT NULL;
+ALTER TABLE pages ADD COLUMN content TEXT NULL DEFAULT NULL;
+ALTER TABLE pages ADD COLUMN authors TEXT NULL DEFAULT NULL;
+ALTER TABLE pages ADD COLUMN categories TEXT NULL DEFAULT NULL;
+ALTER TABLE pages ADD COLUMN media TEXT NULL DEFAULT NULL;
+ALTER TABLE pages ADD COLUMN updated TIMESTAMP NULL DEFAULT NULL;
+
+-- Index summaries and content for full text search
+DROP TRIGGER pages_fts_update;
+DROP TRIGGER pages_fts_delete;
+DROP TRIGGER pages_fts_insert;
+DROP TABLE pages_fts;
+CREATE VIRTUAL TABLE pages_fts USING fts5(
+    title,
+    url,
+    summary,
+    content,
+    content='pages',
+    content_rowid='id'
+);
+INSERT INTO pages_fts(pages_fts) VALUES ('rebuild');
+
+CREATE TRIGGER pages_fts_insert AFTER INSERT ON pages BEGIN
+    INSERT INTO pages_fts (rowid, title, url, summary, content)
+    VALUES (new.id, new.title, new.url, new.summary, new.content);
+END;
+CREATE TRIGGER pages_fts_delete AFTER DELETE ON pages BEGIN
+    INSERT INTO pages_fts (pages_fts, rowid, title, url, summary, content)
+    VALUES ('delete', old.id, old.title, old.url, old.summary, old.content);
+END;
+CREATE TRIGGER pages_fts_update AFTER UPDATE OF title, url, summary, content ON pages BEGIN
+    INSERT INTO pages_fts (pages_fts, rowid, title, url, summary, content)
+    VALUES ('delete', old.id, old.title, old.url, old.summary, old.content);
+    INSERT INTO pages_fts (rowid, title, url, summary, content)
+    VALUES (new.id, new.title, new.url, new.summary, new.content);
+END;
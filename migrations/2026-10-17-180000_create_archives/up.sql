@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE archives (
+    `page_id` INTEGER NOT NULL PRIMARY KEY,
+    `title` TEXT NOT NULL,
+    `html` TEXT NOT NULL,
+    `text` TEXT NOT NULL,
+    `fetched` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (`page_id`) REFERENCES pages (`id`) ON DELETE CASCADE ON UPDATE CASCADE
+);
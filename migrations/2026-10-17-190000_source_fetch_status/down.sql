@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sources DROP COLUMN last_error;
+ALTER TABLE sources DROP COLUMN failures;
+ALTER TABLE sources DROP COLUMN last_success;
+ALTER TABLE sources DROP COLUMN last_attempt;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE sources ADD COLUMN last_attempt TIMESTAMP NULL DEFAULT NULL;
+ALTER TABLE sources ADD COLUMN last_success TIMESTAMP NULL DEFAULT NULL;
+ALTER TABLE sources ADD COLUMN failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE sources ADD COLUMN last_error TEXT NULL DEFAULT NULL;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sources DROP COLUMN refresh_minutes;
+ALTER TABLE sources DROP COLUMN next_fetch;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE sources ADD COLUMN next_fetch TIMESTAMP NULL DEFAULT NULL;
+ALTER TABLE sources ADD COLUMN refresh_minutes INTEGER NULL DEFAULT NULL;
@@ -0,0 +1,204 @@
+use crate::crud::{get_archive, save_archive};
+use crate::models::{Archive, Page};
+use crate::{NetworkError, escape_html, http_agent};
+use chrono::Utc;
+use diesel::SqliteConnection;
+use log::info;
+use scraper::{ElementRef, Html, Node, Selector};
+
+/// Elements that never contain the article itself.
+const SKIPPED: &[&str] = &[
+    "script", "style", "noscript", "nav", "aside", "header", "footer", "form", "button", "iframe",
+    "svg",
+];
+
+/// Elements kept as a block of text in the archive.
+const BLOCKS: &[&str] = &[
+    "p",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "li",
+    "blockquote",
+    "pre",
+];
+
+struct Block {
+    tag: String,
+    text: String,
+}
+
+pub struct Article {
+    pub title: String,
+    pub html: String,
+    pub text: String,
+}
+
+fn element_text(element: ElementRef) -> String {
+    element.text().collect::<Vec<_>>().join(" ")
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Length of the paragraph text directly inside an element. The element
+/// with the most is assumed to hold the article.
+fn paragraph_score(element: ElementRef) -> usize {
+    element
+        .child_elements()
+        .filter(|child| matches!(child.value().name(), "p" | "pre"))
+        .map(|child| collapse_whitespace(&element_text(child)).len())
+        .sum()
+}
+
+fn collect_blocks(element: ElementRef, blocks: &mut Vec<Block>) {
+    for child in element.children() {
+        let Some(child) = ElementRef::wrap(child) else {
+            continue;
+        };
+        let name = child.value().name();
+        if SKIPPED.contains(&name) {
+            continue;
+        }
+        if BLOCKS.contains(&name) {
+            let text = if name == "pre" {
+                element_text(child)
+            } else {
+                collapse_whitespace(&element_text(child))
+            };
+            if !text.trim().is_empty() {
+                blocks.push(Block {
+                    tag: name.to_string(),
+                    text,
+                });
+            }
+        } else {
+            collect_blocks(child, blocks);
+        }
+    }
+}
+
+/// Readability style extraction: find the element with the most paragraph
+/// text and keep only its headings, paragraphs, lists and quotes.
+pub fn extract_article(html: &str) -> Article {
+    let document = Html::parse_document(html);
+    let title = Selector::parse("title, h1")
+        .ok()
+        .and_then(|selector| document.select(&selector).next())
+        .map(|title| collapse_whitespace(&element_text(title)))
+        .unwrap_or_default();
+
+    let containers = Selector::parse("article, main, section, div, td").unwrap();
+    let content = document
+        .select(&containers)
+        .filter(|element| {
+            // Don't pick a container that is itself inside a skipped element
+            !element.ancestors().any(|node| match node.value() {
+                Node::Element(parent) => SKIPPED.contains(&parent.name()),
+                _ => false,
+            })
+        })
+        .max_by_key(|element| paragraph_score(*element))
+        .filter(|element| paragraph_score(*element) > 0)
+        .unwrap_or_else(|| document.root_element());
+
+    let mut blocks = Vec::new();
+    collect_blocks(content, &mut blocks);
+
+    let text = blocks
+        .iter()
+        .map(|block| match block.tag.as_str() {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => format!("## {}", block.text),
+            "li" => format!("• {}", block.text),
+            "blockquote" => format!("> {}", block.text),
+            _ => block.text.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let html = blocks
+        .iter()
+        .map(|block| match block.tag.as_str() {
+            "li" => format!("<p>• {}</p>", escape_html(&block.text)),
+            tag => format!("<{tag}>{}</{tag}>", escape_html(&block.text)),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Article { title, html, text }
+}
+
+fn download_page(url: &str) -> Result<String, ureq::Error> {
+    http_agent()
+        .get(url)
+        .header("User-Agent", "MWR Feed Reader")
+        .call()?
+        .body_mut()
+        .read_to_string()
+}
+
+/// Download a page and extract its article, without storing it.
+pub fn fetch_archive(page: &Page) -> Result<Archive, NetworkError> {
+    let body = download_page(&page.url)?;
+    let article = extract_article(&body);
+    Ok(Archive {
+        page_id: page.id,
+        title: if article.title.is_empty() {
+            page.title.clone()
+        } else {
+            article.title
+        },
+        html: article.html,
+        text: article.text,
+        fetched: Utc::now().naive_utc(),
+    })
+}
+
+/// Download a page, extract its article and store it.
+pub fn archive_page(conn: &mut SqliteConnection, page: &Page) -> Result<Archive, NetworkError> {
+    let archive = fetch_archive(page)?;
+    save_archive(conn, &archive);
+    info!("Archived page {}", page.id);
+    Ok(archive)
+}
+
+/// The stored archive of a page, downloading it first if there isn't one.
+pub fn get_or_archive_page(
+    conn: &mut SqliteConnection,
+    page: &Page,
+) -> Result<Archive, NetworkError> {
+    match get_archive(conn, page.id) {
+        Some(archive) => Ok(archive),
+        None => archive_page(conn, page),
+    }
+}
+
+/// Wrap text to `width` columns for reading in the terminal. Preformatted
+/// text is left alone by only wrapping lines that are too long.
+pub fn wrap_text(text: &str, width: usize) -> String {
+    text.lines()
+        .map(|line| {
+            if line.chars().count() <= width {
+                return line.to_string();
+            }
+            let mut wrapped = String::new();
+            let mut column = 0;
+            for word in line.split_whitespace() {
+                let len = word.chars().count();
+                if column > 0 && column + 1 + len > width {
+                    wrapped.push('\n');
+                    column = 0;
+                } else if column > 0 {
+                    wrapped.push(' ');
+                    column += 1;
+                }
+                wrapped.push_str(word);
+                column += len;
+            }
+            wrapped
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
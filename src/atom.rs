@@ -0,0 +1,153 @@
+use crate::config::FeedConfig;
+use crate::crud::{get_source_by_id, get_upvoted_pages};
+use crate::models::Page;
+use crate::pick_pages;
+use crate::selection::SelectionStrategy;
+use chrono::{NaiveDateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use diesel::SqliteConnection;
+use quick_xml::Writer;
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+
+/// Which pages the published feed contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FeedKind {
+    /// Unread pages picked by the selection strategy (default)
+    #[default]
+    Picks,
+    /// Pages you upvoted, most recently read first
+    Upvoted,
+}
+
+fn rfc3339(date: NaiveDateTime) -> String {
+    date.and_utc().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Pages for the feed. Picks are not marked read, the feed is only a preview.
+pub fn feed_pages(
+    conn: &mut SqliteConnection,
+    strategy: &dyn SelectionStrategy,
+    kind: FeedKind,
+    count: usize,
+) -> Vec<Page> {
+    match kind {
+        FeedKind::Picks => pick_pages(conn, strategy, count),
+        FeedKind::Upvoted => get_upvoted_pages(conn, count as i64),
+    }
+}
+
+/// Write `pages` as an Atom feed. `self_url` is where the feed is served
+/// from, if anywhere.
+pub fn atom_feed(
+    conn: &mut SqliteConnection,
+    config: &FeedConfig,
+    kind: FeedKind,
+    pages: &[Page],
+    self_url: Option<&str>,
+) -> io::Result<String> {
+    let mut source_titles = HashMap::new();
+    for page in pages {
+        source_titles.entry(page.source_id).or_insert_with(|| {
+            get_source_by_id(conn, page.source_id)
+                .ok()
+                .flatten()
+                .map(|source| source.title)
+                .unwrap_or_default()
+        });
+    }
+    let feed_id = match kind {
+        FeedKind::Picks => "urn:mwr:picks",
+        FeedKind::Upvoted => "urn:mwr:upvoted",
+    };
+
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer
+        .create_element("feed")
+        .with_attribute(("xmlns", "http://www.w3.org/2005/Atom"))
+        .write_inner_content(|writer| {
+            writer
+                .create_element("title")
+                .write_text_content(BytesText::new(&config.title))?;
+            writer
+                .create_element("id")
+                .write_text_content(BytesText::new(feed_id))?;
+            writer
+                .create_element("updated")
+                .write_text_content(BytesText::new(&rfc3339(Utc::now().naive_utc())))?;
+            writer
+                .create_element("generator")
+                .write_text_content(BytesText::new("My Web Ring"))?;
+            if let Some(self_url) = self_url {
+                writer
+                    .create_element("link")
+                    .with_attributes([("rel", "self"), ("href", self_url)])
+                    .write_empty()?;
+            }
+            for page in pages {
+                write_entry(writer, page, &source_titles[&page.source_id])?;
+            }
+            Ok(())
+        })?;
+    Ok(String::from_utf8_lossy(&writer.into_inner()).to_string())
+}
+
+fn write_entry(writer: &mut Writer<Vec<u8>>, page: &Page, source_title: &str) -> io::Result<()> {
+    let authors: Vec<&str> = match &page.authors {
+        Some(authors) => authors.split(", ").collect(),
+        None => vec![source_title],
+    };
+    writer
+        .create_element("entry")
+        .write_inner_content(|writer| {
+            writer
+                .create_element("title")
+                .write_text_content(BytesText::new(&page.title))?;
+            writer
+                .create_element("id")
+                .write_text_content(BytesText::new(&page.url))?;
+            writer
+                .create_element("link")
+                .with_attribute(("href", page.url.as_str()))
+                .write_empty()?;
+            writer
+                .create_element("updated")
+                .write_text_content(BytesText::new(&rfc3339(page.updated.unwrap_or(page.date))))?;
+            for author in &authors {
+                writer
+                    .create_element("author")
+                    .write_inner_content(|writer| {
+                        writer
+                            .create_element("name")
+                            .write_text_content(BytesText::new(author))?;
+                        Ok(())
+                    })?;
+            }
+            for category in page.categories.iter().flat_map(|c| c.split(", ")) {
+                writer
+                    .create_element("category")
+                    .with_attribute(("term", category))
+                    .write_empty()?;
+            }
+            if let Some(summary) = &page.summary {
+                writer
+                    .create_element("summary")
+                    .with_attribute(("type", "html"))
+                    .write_text_content(BytesText::new(summary))?;
+            }
+            writer
+                .create_element("source")
+                .write_inner_content(|writer| {
+                    writer
+                        .create_element("title")
+                        .write_text_content(BytesText::new(source_title))?;
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    Ok(())
+}
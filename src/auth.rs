@@ -0,0 +1,217 @@
+use crate::config::AuthConfig;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use pbkdf2::pbkdf2_hmac;
+use rand::RngCore;
+use sha2::Sha256;
+
+const SALT_BYTES: usize = 16;
+const HASH_BYTES: usize = 32;
+/// PBKDF2 rounds for new hashes. Each hash stores its own count, so this can
+/// be raised without breaking existing ones.
+const ITERATIONS: u32 = 100_000;
+
+/// Whether a route only reads or also changes what's stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn derive(salt: &[u8], iterations: u32, secret: &str) -> [u8; HASH_BYTES] {
+    let mut hash = [0u8; HASH_BYTES];
+    pbkdf2_hmac::<Sha256>(secret.as_bytes(), salt, iterations, &mut hash);
+    hash
+}
+
+/// Compare without returning early so timing doesn't leak the hash.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Hash a password or token for the config file, as
+/// "pbkdf2-sha256$<iterations>$<salt>$<hash>".
+pub fn hash_secret(secret: &str) -> String {
+    let mut salt = [0u8; SALT_BYTES];
+    rand::rng().fill_bytes(&mut salt);
+    format!(
+        "pbkdf2-sha256${}${}${}",
+        ITERATIONS,
+        to_hex(&salt),
+        to_hex(&derive(&salt, ITERATIONS, secret))
+    )
+}
+
+/// Check a secret against a hash made by `hash_secret`.
+pub fn verify_secret(secret: &str, hash: &str) -> bool {
+    let mut parts = hash.split('$');
+    let (Some("pbkdf2-sha256"), Some(iterations), Some(salt), Some(expected), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return false;
+    };
+    match (iterations.parse(), from_hex(salt), from_hex(expected)) {
+        (Ok(iterations), Some(salt), Some(expected)) if iterations > 0 => {
+            constant_time_eq(&derive(&salt, iterations, secret), &expected)
+        }
+        _ => false,
+    }
+}
+
+impl AuthConfig {
+    /// True if any credentials are configured.
+    pub fn enabled(&self) -> bool {
+        self.token_hash.is_some() || self.password_hash.is_some()
+    }
+
+    pub fn required(&self, access: Access) -> bool {
+        self.enabled() && (access == Access::Write || self.protect_reads)
+    }
+
+    /// Check an `Authorization` header, either "Bearer <token>" or Basic.
+    pub fn authorized(&self, authorization: Option<&str>) -> bool {
+        let Some((scheme, credentials)) = authorization.and_then(|value| value.split_once(' '))
+        else {
+            return false;
+        };
+        let credentials = credentials.trim();
+        if scheme.eq_ignore_ascii_case("bearer") {
+            return self
+                .token_hash
+                .as_deref()
+                .is_some_and(|hash| verify_secret(credentials, hash));
+        }
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return false;
+        }
+        let Some(decoded) = STANDARD
+            .decode(credentials)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+        else {
+            return false;
+        };
+        let Some((username, password)) = decoded.split_once(':') else {
+            return false;
+        };
+        let username_matches = self
+            .username
+            .as_deref()
+            .is_none_or(|name| constant_time_eq(name.as_bytes(), username.as_bytes()));
+        let password_matches = self
+            .password_hash
+            .as_deref()
+            .is_some_and(|hash| verify_secret(password, hash));
+        username_matches && password_matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A hash like `hash_secret` makes, with few rounds so tests stay fast.
+    fn quick_hash(secret: &str) -> String {
+        let salt = [7u8; SALT_BYTES];
+        format!(
+            "pbkdf2-sha256$2${}${}",
+            to_hex(&salt),
+            to_hex(&derive(&salt, 2, secret))
+        )
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", STANDARD.encode(credentials))
+    }
+
+    fn config(username: Option<&str>) -> AuthConfig {
+        AuthConfig {
+            username: username.map(str::to_string),
+            password_hash: Some(quick_hash("hunter2")),
+            token_hash: Some(quick_hash("token")),
+            protect_reads: false,
+        }
+    }
+
+    #[test]
+    fn hash_secret_round_trips() {
+        let hash = hash_secret("correct horse");
+        assert!(hash.starts_with(&format!("pbkdf2-sha256${}$", ITERATIONS)));
+        assert!(verify_secret("correct horse", &hash));
+        assert!(!verify_secret("correct horse ", &hash));
+        // Salted, so the same secret hashes differently
+        assert_ne!(hash, hash_secret("correct horse"));
+    }
+
+    #[test]
+    fn verify_secret_rejects_malformed_hashes() {
+        let hash = quick_hash("secret");
+        assert!(verify_secret("secret", &hash));
+        assert!(!verify_secret("secret", &format!("{}$00", hash)));
+        assert!(!verify_secret(
+            "secret",
+            &hash.replacen("pbkdf2-sha256", "sha256", 1)
+        ));
+        assert!(!verify_secret("secret", &hash.replacen("$2$", "$0$", 1)));
+        assert!(!verify_secret("secret", &hash.replacen("$2$", "$two$", 1)));
+        assert!(!verify_secret("secret", &hash[..hash.len() - 1]));
+        assert!(!verify_secret("secret", ""));
+    }
+
+    #[test]
+    fn from_hex_needs_pairs_of_hex_digits() {
+        assert_eq!(from_hex("00ff10"), Some(vec![0, 255, 16]));
+        assert_eq!(from_hex(""), Some(vec![]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex("é0"), None);
+    }
+
+    #[test]
+    fn basic_auth_checks_username_and_password() {
+        let auth = config(Some("me"));
+        assert!(auth.authorized(Some(&basic("me:hunter2"))));
+        assert!(!auth.authorized(Some(&basic("me:hunter3"))));
+        assert!(!auth.authorized(Some(&basic("you:hunter2"))));
+        assert!(!auth.authorized(Some(&basic("me"))));
+        assert!(!auth.authorized(Some("Basic not-base64!")));
+        assert!(!auth.authorized(None));
+    }
+
+    #[test]
+    fn basic_auth_accepts_any_username_when_unset() {
+        let auth = config(None);
+        assert!(auth.authorized(Some(&basic("anyone:hunter2"))));
+        assert!(auth.authorized(Some(&basic(":hunter2"))));
+        assert!(!auth.authorized(Some(&basic("anyone:wrong"))));
+    }
+
+    #[test]
+    fn bearer_and_basic_use_their_own_secret() {
+        let auth = config(Some("me"));
+        assert!(auth.authorized(Some("Bearer token")));
+        assert!(auth.authorized(Some("bearer  token ")));
+        assert!(!auth.authorized(Some("Bearer hunter2")));
+        assert!(!auth.authorized(Some(&basic("me:token"))));
+        assert!(!auth.authorized(Some("Token token")));
+        assert!(!auth.authorized(Some("Bearer")));
+    }
+}
@@ -1,47 +1,284 @@
-use crate::data_locations;
-use std::io::{self, Read, Write};
-use std::process::{Command, Stdio};
-
-pub fn backup() {
-    let database_location = data_locations().database;
-    let output = Command::new("sqlite3")
-        .arg(database_location)
-        .arg(".dump")
-        .output()
-        .expect("Failed. Make sure sqlite3 is istalled.");
-
-    print!("{}", String::from_utf8_lossy(&output.stdout));
-}
-
-pub fn restore() {
-    let database_location = data_locations().database;
-    let mut sql_dump = String::new();
-    io::stdin()
-        .read_to_string(&mut sql_dump)
-        .expect("Failed to read input");
-
-    let mut child = Command::new("sqlite3")
-        .arg(database_location)
-        .stdin(Stdio::piped())
-        .spawn()
-        .expect("Failed. Make sure sqlite3 is installed.");
-
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin
-            .write_all(sql_dump.as_bytes())
-            .expect("Failed to write to stdin");
-    }
-
-    let output = child
-        .wait_with_output()
-        .expect("Failed to wait for child process");
-
-    if !output.status.success() {
-        eprintln!(
-            "Restore failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-    } else {
-        println!("Restore successful");
+use crate::MIGRATIONS;
+use crate::models::{HistoryAction, Page, Source};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_migrations::MigrationHarness;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::{error, fmt};
+
+/// Bump when the layout of `Backup` changes in a way older versions can't read.
+/// Version 2 added tags and history.
+pub const BACKUP_FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub struct BackupError {
+    message: String,
+}
+
+impl BackupError {
+    fn new(message: String) -> Self {
+        BackupError { message }
+    }
+}
+
+impl From<serde_json::Error> for BackupError {
+    fn from(error: serde_json::Error) -> Self {
+        BackupError::new(error.to_string())
+    }
+}
+
+impl From<diesel::result::Error> for BackupError {
+    fn from(error: diesel::result::Error) -> Self {
+        BackupError::new(error.to_string())
     }
 }
+
+impl From<Box<dyn error::Error + Send + Sync>> for BackupError {
+    fn from(error: Box<dyn error::Error + Send + Sync>) -> Self {
+        BackupError::new(error.to_string())
+    }
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Backup error: {}", self.message)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Backup {
+    pub format_version: u32,
+    /// Latest migration applied to the database the backup was taken from.
+    pub schema_version: String,
+    pub created: NaiveDateTime,
+    pub sources: Vec<Source>,
+    pub pages: Vec<Page>,
+    /// Tag names by source url. Missing from version 1 backups.
+    #[serde(default)]
+    pub tags: BTreeMap<String, Vec<String>>,
+    /// Missing from version 1 backups.
+    #[serde(default)]
+    pub history: Vec<BackupHistoryEntry>,
+}
+
+/// A history entry with its page and source by url, since ids change on
+/// restore.
+#[derive(Serialize, Deserialize)]
+pub struct BackupHistoryEntry {
+    pub page_url: String,
+    pub source_url: String,
+    pub action: HistoryAction,
+    pub created: NaiveDateTime,
+}
+
+pub struct RestoreSummary {
+    pub sources: usize,
+    pub pages: usize,
+    pub history: usize,
+}
+
+fn schema_version(conn: &mut SqliteConnection) -> Result<String, BackupError> {
+    let applied = conn.applied_migrations()?;
+    Ok(applied
+        .iter()
+        .map(|version| version.to_string())
+        .max()
+        .unwrap_or_default())
+}
+
+/// Serialize all sources, pages, tags and history as JSON.
+pub fn backup(conn: &mut SqliteConnection) -> Result<String, BackupError> {
+    use crate::schema::{history, pages, source_tags, sources, tags};
+
+    let mut source_tags: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let tagged: Vec<(String, String)> = source_tags::table
+        .inner_join(sources::table)
+        .inner_join(tags::table)
+        .select((sources::url, tags::name))
+        .order((sources::url.asc(), tags::name.asc()))
+        .load(conn)?;
+    for (url, tag) in tagged {
+        source_tags.entry(url).or_default().push(tag);
+    }
+
+    let backup = Backup {
+        format_version: BACKUP_FORMAT_VERSION,
+        schema_version: schema_version(conn)?,
+        created: Utc::now().naive_utc(),
+        sources: sources::table
+            .order(sources::id.asc())
+            .select(Source::as_select())
+            .load(conn)?,
+        pages: pages::table
+            .order(pages::id.asc())
+            .select(Page::as_select())
+            .load(conn)?,
+        tags: source_tags,
+        history: history::table
+            .inner_join(pages::table)
+            .inner_join(sources::table)
+            .select((pages::url, sources::url, history::action, history::created))
+            .order(history::id.asc())
+            .load::<(String, String, HistoryAction, NaiveDateTime)>(conn)?
+            .into_iter()
+            .map(
+                |(page_url, source_url, action, created)| BackupHistoryEntry {
+                    page_url,
+                    source_url,
+                    action,
+                    created,
+                },
+            )
+            .collect(),
+    };
+    Ok(serde_json::to_string_pretty(&backup)?)
+}
+
+/// Restore a backup produced by `backup`. Sources, pages, tags and history
+/// are merged into the existing database by url, existing rows are left
+/// untouched. With `replace` all existing sources and pages, and with them
+/// their tags and history, are deleted first.
+pub fn restore(
+    conn: &mut SqliteConnection,
+    input: &str,
+    replace: bool,
+) -> Result<RestoreSummary, BackupError> {
+    use crate::schema::{history, pages, source_tags, sources, tags};
+
+    conn.run_pending_migrations(MIGRATIONS)?;
+    let backup: Backup = serde_json::from_str(input)?;
+    if backup.format_version == 0 || backup.format_version > BACKUP_FORMAT_VERSION {
+        return Err(BackupError::new(format!(
+            "Unsupported backup format version {}, expected at most {}",
+            backup.format_version, BACKUP_FORMAT_VERSION
+        )));
+    }
+    let current_version = schema_version(conn)?;
+    if backup.schema_version > current_version {
+        return Err(BackupError::new(format!(
+            "Backup schema version {} is newer than database schema version {}",
+            backup.schema_version, current_version
+        )));
+    }
+
+    conn.transaction(|conn| {
+        if replace {
+            diesel::delete(pages::table).execute(conn)?;
+            diesel::delete(sources::table).execute(conn)?;
+        }
+
+        let mut summary = RestoreSummary {
+            sources: 0,
+            pages: 0,
+            history: 0,
+        };
+        // Source ids in the backup mapped to ids in this database
+        let mut source_ids = HashMap::new();
+        let mut source_ids_by_url = HashMap::new();
+        for source in &backup.sources {
+            summary.sources += diesel::insert_into(sources::table)
+                .values((
+                    sources::s_type.eq(source.s_type),
+                    sources::weight.eq(source.weight),
+                    sources::url.eq(&source.url),
+                    sources::last_modified.eq(source.last_modified),
+                    sources::etag.eq(&source.etag),
+                    sources::added.eq(source.added),
+                    sources::title.eq(&source.title),
+                    sources::folder.eq(&source.folder),
+                ))
+                .on_conflict(sources::url)
+                .do_nothing()
+                .execute(conn)?;
+            let id: i32 = sources::table
+                .filter(sources::url.eq(&source.url))
+                .select(sources::id)
+                .first(conn)?;
+            source_ids.insert(source.id, id);
+            source_ids_by_url.insert(source.url.as_str(), id);
+        }
+
+        for page in &backup.pages {
+            let Some(source_id) = source_ids.get(&page.source_id) else {
+                continue;
+            };
+            summary.pages += diesel::insert_into(pages::table)
+                .values((
+                    pages::source_id.eq(source_id),
+                    pages::url.eq(&page.url),
+                    pages::title.eq(&page.title),
+                    pages::read.eq(page.read),
+                    pages::date.eq(page.date),
+                    pages::added.eq(page.added),
+                    pages::vote.eq(page.vote),
+                    pages::summary.eq(&page.summary),
+                    pages::content.eq(&page.content),
+                    pages::authors.eq(&page.authors),
+                    pages::categories.eq(&page.categories),
+                    pages::media.eq(&page.media),
+                    pages::updated.eq(page.updated),
+                ))
+                .on_conflict(pages::url)
+                .do_nothing()
+                .execute(conn)?;
+        }
+
+        for (url, names) in &backup.tags {
+            let Some(source_id) = source_ids_by_url.get(url.as_str()) else {
+                continue;
+            };
+            for name in names {
+                diesel::insert_into(tags::table)
+                    .values(tags::name.eq(name))
+                    .on_conflict(tags::name)
+                    .do_nothing()
+                    .execute(conn)?;
+                let tag_id: i32 = tags::table
+                    .filter(tags::name.eq(name))
+                    .select(tags::id)
+                    .first(conn)?;
+                diesel::insert_into(source_tags::table)
+                    .values((
+                        source_tags::source_id.eq(source_id),
+                        source_tags::tag_id.eq(tag_id),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+            }
+        }
+
+        for entry in &backup.history {
+            let Some(source_id) = source_ids_by_url.get(entry.source_url.as_str()) else {
+                continue;
+            };
+            let Some(page_id) = pages::table
+                .filter(pages::url.eq(&entry.page_url))
+                .select(pages::id)
+                .first::<i32>(conn)
+                .optional()?
+            else {
+                continue;
+            };
+            // History has no natural key, skip entries restored before
+            let restored: i64 = history::table
+                .filter(history::page_id.eq(page_id))
+                .filter(history::action.eq(entry.action))
+                .filter(history::created.eq(entry.created))
+                .count()
+                .get_result(conn)?;
+            if restored > 0 {
+                continue;
+            }
+            summary.history += diesel::insert_into(history::table)
+                .values((
+                    history::page_id.eq(page_id),
+                    history::source_id.eq(source_id),
+                    history::action.eq(entry.action),
+                    history::created.eq(entry.created),
+                ))
+                .execute(conn)?;
+        }
+        Ok(summary)
+    })
+}
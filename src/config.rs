@@ -0,0 +1,190 @@
+use crate::atom::FeedKind;
+use crate::selection::SelectionConfig;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+#[derive(Debug)]
+pub struct ConfigError {
+    message: String,
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(error: io::Error) -> Self {
+        ConfigError {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(error: toml::de::Error) -> Self {
+        ConfigError {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Config error: {}", self.message)
+    }
+}
+
+/// Settings read from `config.toml`. Every section and key is optional.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Set to "debug" to log how each selected page was scored.
+    pub log_level: LevelFilter,
+    pub selection: SelectionConfig,
+    pub votes: VoteConfig,
+    pub sync: SyncConfig,
+    pub auth: AuthConfig,
+    pub feed: FeedConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            log_level: LevelFilter::Info,
+            selection: SelectionConfig::default(),
+            votes: VoteConfig::default(),
+            sync: SyncConfig::default(),
+            auth: AuthConfig::default(),
+            feed: FeedConfig::default(),
+        }
+    }
+}
+
+/// How page votes move the weight of their source.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct VoteConfig {
+    pub min_weight: i32,
+    pub max_weight: i32,
+    /// Weight added or removed per vote.
+    pub step: i32,
+}
+
+impl Default for VoteConfig {
+    fn default() -> Self {
+        VoteConfig {
+            min_weight: 0,
+            max_weight: 100,
+            step: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    /// Download and store a reader mode copy of new pages when syncing.
+    pub archive: bool,
+    /// Wait after a source first fails to fetch, doubled for every further
+    /// failure.
+    pub backoff_minutes: i64,
+    /// Longest wait between attempts for a failing source.
+    pub max_backoff_hours: i64,
+    /// Shortest wait before fetching a source again, whatever the feed or
+    /// server asks for.
+    pub min_interval_minutes: i64,
+    /// Longest wait before fetching a source again.
+    pub max_interval_hours: i64,
+    /// Sources downloaded at the same time, never more than one per host.
+    pub workers: usize,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            archive: false,
+            backoff_minutes: 30,
+            max_backoff_hours: 24 * 7,
+            min_interval_minutes: 60,
+            max_interval_hours: 24,
+            workers: 4,
+        }
+    }
+}
+
+/// Credentials for the HTTP server. Without a password or token hash the
+/// server is open to anyone who can reach it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Username for Basic auth, any username is accepted if unset.
+    pub username: Option<String>,
+    /// Basic auth password, hashed with `mwr hash-password`.
+    pub password_hash: Option<String>,
+    /// Bearer token for scripts, hashed with `mwr hash-password`.
+    pub token_hash: Option<String>,
+    /// Also require credentials for routes that don't change anything.
+    pub protect_reads: bool,
+}
+
+/// What `/feed.xml` and `mwr export-feed` publish.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct FeedConfig {
+    pub kind: FeedKind,
+    /// Number of entries in the feed.
+    pub count: usize,
+    pub title: String,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        FeedConfig {
+            kind: FeedKind::default(),
+            count: 20,
+            title: "My Web Ring".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file, or the defaults if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let contents = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Catch values that parse but would break selection or syncing.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let half_life_days = self.selection.half_life_days;
+        if !half_life_days.is_finite() || half_life_days <= 0.0 {
+            return Err(ConfigError {
+                message: format!(
+                    "selection.half_life_days must be a positive number, got {}",
+                    half_life_days
+                ),
+            });
+        }
+        let unread_exponent = self.selection.unread_exponent;
+        if !(0.0..=1.0).contains(&unread_exponent) {
+            return Err(ConfigError {
+                message: format!(
+                    "selection.unread_exponent must be between 0 and 1, got {}",
+                    unread_exponent
+                ),
+            });
+        }
+        if self.votes.min_weight > self.votes.max_weight {
+            return Err(ConfigError {
+                message: format!(
+                    "votes.min_weight ({}) is larger than votes.max_weight ({})",
+                    self.votes.min_weight, self.votes.max_weight
+                ),
+            });
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,9 @@
-use crate::models::{NewPage, NewSource, Page, Source, SourceType};
+use crate::config::VoteConfig;
+use crate::models::{
+    Archive, HistoryAction, HistoryEntry, NewHistoryEntry, NewPage, NewSource, Page, SearchResult,
+    Source, SourceType, Tag,
+};
+use crate::selection::Candidate;
 use chrono::{Duration, NaiveDateTime, Utc};
 use diesel::{
     dsl::now,
@@ -7,21 +12,25 @@ use diesel::{
         DatabaseErrorKind,
         Error::{DatabaseError, NotFound},
     },
+    sql_types::{BigInt, Text},
 };
 use std::cmp;
+use std::collections::HashMap;
 
 pub fn create_source(
     conn: &mut SqliteConnection,
     url: &str,
     s_type: SourceType,
     title: String,
-) -> Source {
+) -> QueryResult<Source> {
     use crate::schema::sources;
 
     let new_post = NewSource {
         url: url.to_string(),
         s_type,
         title,
+        weight: None,
+        folder: None,
     };
 
     match diesel::insert_into(sources::table)
@@ -29,78 +38,176 @@ pub fn create_source(
         .returning(Source::as_returning())
         .get_result(conn)
     {
-        Ok(source) => source,
+        Ok(source) => Ok(source),
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            get_source_by_url(conn, url)?.ok_or(NotFound)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Inserts a source, returning None instead of the existing row if the url
+/// is already present.
+pub fn insert_source(conn: &mut SqliteConnection, new_source: &NewSource) -> Option<Source> {
+    use crate::schema::sources;
+
+    match diesel::insert_into(sources::table)
+        .values(new_source)
+        .returning(Source::as_returning())
+        .get_result(conn)
+    {
+        Ok(source) => Some(source),
         Err(err) => match err {
-            DatabaseError(DatabaseErrorKind::UniqueViolation, _) => get_source_by_url(conn, url)
-                .expect("Unique violation, but could not find matching source"),
+            DatabaseError(DatabaseErrorKind::UniqueViolation, _) => None,
             _ => panic!("Database error: {}", err),
         },
     }
 }
 
-fn get_source_by_url(conn: &mut SqliteConnection, source_url: &str) -> Option<Source> {
+pub fn get_source_by_url(
+    conn: &mut SqliteConnection,
+    source_url: &str,
+) -> QueryResult<Option<Source>> {
     use crate::schema::sources::dsl::*;
 
-    match sources
+    sources
         .filter(url.eq(source_url))
         .select(Source::as_select())
         .first(conn)
-    {
-        Ok(source) => Some(source),
-        Err(err) => match err {
-            NotFound => None,
-            _ => panic!("Database error: {}", err),
-        },
-    }
+        .optional()
 }
 
-pub fn get_source_by_id(conn: &mut SqliteConnection, source_id: i32) -> Option<Source> {
+pub fn get_source_by_id(
+    conn: &mut SqliteConnection,
+    source_id: i32,
+) -> QueryResult<Option<Source>> {
     use crate::schema::sources::dsl::*;
 
-    match sources
+    sources
         .filter(id.eq(source_id))
         .select(Source::as_select())
         .first(conn)
-    {
-        Ok(source) => Some(source),
-        Err(err) => match err {
-            NotFound => None,
-            _ => panic!("Database error: {}", err),
-        },
-    }
+        .optional()
 }
 
-pub fn get_page_by_id(conn: &mut SqliteConnection, page_id: i32) -> Option<Page> {
+pub fn get_page_by_id(conn: &mut SqliteConnection, page_id: i32) -> QueryResult<Option<Page>> {
     use crate::schema::pages::dsl::*;
 
-    match pages
+    pages
         .filter(id.eq(page_id))
         .select(Page::as_select())
         .first(conn)
-    {
-        Ok(page) => Some(page),
-        Err(err) => match err {
-            NotFound => None,
-            _ => panic!("Database error: {}", err),
-        },
-    }
+        .optional()
 }
 
-pub fn delete_source(conn: &mut SqliteConnection, source_id: i32) -> Result<String, String> {
-    if let Some(source) = get_source_by_id(conn, source_id) {
-        diesel::delete(&source)
-            .execute(conn)
-            .expect("Error deleting source");
-        Ok(source.url)
-    } else {
-        Err("Source not found".to_string())
+/// Delete a source, returning its url, or None if there is no such source.
+pub fn delete_source(conn: &mut SqliteConnection, source_id: i32) -> QueryResult<Option<String>> {
+    match get_source_by_id(conn, source_id)? {
+        Some(source) => {
+            diesel::delete(&source).execute(conn)?;
+            Ok(Some(source.url))
+        }
+        None => Ok(None),
     }
 }
 
-pub fn get_sources(conn: &mut SqliteConnection) -> Vec<Source> {
+pub fn get_sources(conn: &mut SqliteConnection) -> QueryResult<Vec<Source>> {
+    use crate::schema::sources::dsl::*;
+
+    sources
+        .order(added.desc())
+        .select(Source::as_select())
+        .load(conn)
+}
+
+/// Sources with the given tag.
+pub fn get_sources_by_tag(conn: &mut SqliteConnection, tag: &str) -> Vec<Source> {
     use crate::schema::sources::dsl::*;
+    use crate::schema::{source_tags, tags};
 
+    let tagged = source_tags::table
+        .inner_join(tags::table)
+        .filter(tags::name.eq(tag))
+        .select(source_tags::source_id);
     sources
+        .filter(id.eq_any(tagged))
+        .order(added.desc())
+        .select(Source::as_select())
+        .load(conn)
+        .expect("Error loading sources")
+}
+
+/// Add a tag to a source, creating the tag if needed. Returns false if the
+/// source already had it.
+pub fn tag_source(conn: &mut SqliteConnection, i_source_id: i32, tag: &str) -> bool {
+    use crate::schema::{source_tags, tags};
+
+    diesel::insert_into(tags::table)
+        .values(tags::name.eq(tag))
+        .on_conflict(tags::name)
+        .do_nothing()
+        .execute(conn)
+        .expect("Error creating tag");
+    let tag = tags::table
+        .filter(tags::name.eq(tag))
+        .select(Tag::as_select())
+        .first(conn)
+        .expect("Error loading tag");
+    diesel::insert_into(source_tags::table)
+        .values((
+            source_tags::source_id.eq(i_source_id),
+            source_tags::tag_id.eq(tag.id),
+        ))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .expect("Error tagging source")
+        > 0
+}
+
+/// Remove a tag from a source. Returns false if the source didn't have it.
+pub fn untag_source(conn: &mut SqliteConnection, i_source_id: i32, tag: &str) -> bool {
+    use crate::schema::{source_tags, tags};
+
+    let tag_ids = tags::table.filter(tags::name.eq(tag)).select(tags::id);
+    diesel::delete(
+        source_tags::table
+            .filter(source_tags::source_id.eq(i_source_id))
+            .filter(source_tags::tag_id.eq_any(tag_ids)),
+    )
+    .execute(conn)
+    .expect("Error untagging source")
+        > 0
+}
+
+pub fn get_tags_for_source(
+    conn: &mut SqliteConnection,
+    i_source_id: i32,
+) -> QueryResult<Vec<String>> {
+    use crate::schema::{source_tags, tags};
+
+    source_tags::table
+        .inner_join(tags::table)
+        .filter(source_tags::source_id.eq(i_source_id))
+        .select(tags::name)
+        .order(tags::name.asc())
+        .load(conn)
+}
+
+/// Sources in a folder, including any folders nested below it.
+pub fn get_sources_by_folder(conn: &mut SqliteConnection, i_folder: &str) -> Vec<Source> {
+    use crate::schema::sources::dsl::*;
+
+    // Subfolders too, with the folder name matched literally
+    let escaped = i_folder
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    sources
+        .filter(
+            folder
+                .eq(i_folder)
+                .or(folder.like(format!("{}/%", escaped)).escape('\\')),
+        )
         .order(added.desc())
         .select(Source::as_select())
         .load(conn)
@@ -112,62 +219,129 @@ pub fn mark_source_synced(
     marked_source: &Source,
     i_last_modified: Option<NaiveDateTime>,
     i_etag: Option<String>,
-) -> Source {
+    i_refresh_minutes: Option<i32>,
+) -> QueryResult<Source> {
     use crate::schema::sources::dsl::*;
 
     diesel::update(&marked_source)
-        .set((last_modified.eq(i_last_modified), etag.eq(i_etag)))
+        .set((
+            last_modified.eq(i_last_modified),
+            etag.eq(i_etag),
+            refresh_minutes.eq(i_refresh_minutes),
+        ))
         .returning(Source::as_returning())
         .get_result(conn)
-        .expect("Error marking source as synced")
 }
 
-pub fn create_pages(conn: &mut SqliteConnection, new_pages: Vec<NewPage>) -> usize {
+pub fn record_fetch_success(conn: &mut SqliteConnection, i_source_id: i32) {
+    use crate::schema::sources::dsl::*;
+
+    let fetched = Utc::now().naive_utc();
+    diesel::update(sources.find(i_source_id))
+        .set((
+            last_attempt.eq(fetched),
+            last_success.eq(fetched),
+            failures.eq(0),
+            last_error.eq(None::<String>),
+        ))
+        .execute(conn)
+        .expect("Error recording fetch success");
+}
+
+pub fn record_fetch_failure(conn: &mut SqliteConnection, i_source_id: i32, error: &str) {
+    use crate::schema::sources::dsl::*;
+
+    diesel::update(sources.find(i_source_id))
+        .set((
+            last_attempt.eq(Utc::now().naive_utc()),
+            failures.eq(failures + 1),
+            last_error.eq(error),
+        ))
+        .execute(conn)
+        .expect("Error recording fetch failure");
+}
+
+pub fn schedule_source(conn: &mut SqliteConnection, i_source_id: i32, i_next_fetch: NaiveDateTime) {
+    use crate::schema::sources::dsl::*;
+
+    diesel::update(sources.find(i_source_id))
+        .set(next_fetch.eq(i_next_fetch))
+        .execute(conn)
+        .expect("Error scheduling source");
+}
+
+/// Sources whose last fetch failed, most failures first.
+pub fn get_broken_sources(conn: &mut SqliteConnection) -> Vec<Source> {
+    use crate::schema::sources::dsl::*;
+
+    sources
+        .filter(failures.gt(0))
+        .order((failures.desc(), id.asc()))
+        .select(Source::as_select())
+        .load(conn)
+        .expect("Error loading sources")
+}
+
+/// Insert pages, skipping urls that already exist. Returns the pages that
+/// were actually inserted.
+pub fn create_pages(
+    conn: &mut SqliteConnection,
+    new_pages: Vec<NewPage>,
+) -> QueryResult<Vec<Page>> {
     use crate::schema::pages;
 
-    let mut count = 0;
-    new_pages.iter().for_each(|new_page| {
-        count += match diesel::insert_into(pages::table)
+    let mut created = Vec::new();
+    for new_page in &new_pages {
+        match diesel::insert_into(pages::table)
             .values(new_page)
-            .execute(conn)
+            .returning(Page::as_returning())
+            .get_result(conn)
         {
-            Ok(count) => count,
-            Err(err) => match err {
-                DatabaseError(DatabaseErrorKind::UniqueViolation, _) => 0,
-                _ => panic!("Database error: {}", err),
-            },
+            Ok(page) => created.push(page),
+            Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {}
+            Err(err) => return Err(err),
         }
-    });
-    count
+    }
+    Ok(created)
 }
 
-/// Creates a page but if the url exists, set it as unread and the date
-/// as 5 days ago so they don't get buried.
-pub fn create_or_reset_page(conn: &mut SqliteConnection, new_page: NewPage) -> usize {
+/// Creates a page but if the url exists, set it as unread. Either way the
+/// date is set to 5 days ago so they don't get buried, and are scored for
+/// recency the same way as feed pages of that age.
+pub fn create_or_reset_page(
+    conn: &mut SqliteConnection,
+    mut new_page: NewPage,
+) -> QueryResult<usize> {
     use crate::schema::pages::dsl::*;
 
+    let reset_date = (Utc::now() - Duration::days(5)).naive_utc();
+    new_page.date = new_page.date.or(Some(reset_date));
     diesel::insert_into(pages)
         .values(&new_page)
         .on_conflict(url)
         .do_update()
-        .set((
-            read.eq(Option::<NaiveDateTime>::None),
-            date.eq((Utc::now() - Duration::days(5)).naive_utc()),
-        ))
+        .set((read.eq(Option::<NaiveDateTime>::None), date.eq(reset_date)))
         .execute(conn)
-        .expect("Unexpected database error create_single_page")
 }
 
-pub fn get_pages(conn: &mut SqliteConnection, unread: bool) -> Vec<Page> {
+pub fn get_pages(
+    conn: &mut SqliteConnection,
+    unread: bool,
+    i_source_id: Option<i32>,
+) -> QueryResult<Vec<Page>> {
     use crate::schema::pages::dsl::*;
 
-    let query = pages.order(added.desc()).select(Page::as_select());
+    let mut query = pages
+        .order(added.desc())
+        .select(Page::as_select())
+        .into_boxed();
     if unread {
-        query.filter(read.is_null()).get_results(conn)
-    } else {
-        query.get_results(conn)
+        query = query.filter(read.is_null());
+    }
+    if let Some(i_source_id) = i_source_id {
+        query = query.filter(source_id.eq(i_source_id));
     }
-    .expect("Error loading pages")
+    query.get_results(conn)
 }
 
 pub fn get_unread_pages_by_source(conn: &mut SqliteConnection, i_source_id: i32) -> Vec<Page> {
@@ -182,13 +356,12 @@ pub fn get_unread_pages_by_source(conn: &mut SqliteConnection, i_source_id: i32)
         .expect("Error loading pages")
 }
 
-pub fn mark_page_read(conn: &mut SqliteConnection, page: &Page) -> Page {
+pub fn mark_page_read(conn: &mut SqliteConnection, page: &Page) -> QueryResult<Page> {
     use crate::schema::pages::dsl::*;
     diesel::update(page)
         .set(read.eq(now))
         .returning(Page::as_returning())
         .get_result(conn)
-        .expect("Error setting page read.")
 }
 
 pub fn mark_source_read(conn: &mut SqliteConnection, i_source_id: i32) -> Vec<Page> {
@@ -210,10 +383,131 @@ pub fn mark_page_unread(conn: &mut SqliteConnection, page: &Page) -> Page {
         .expect("Error setting page read.")
 }
 
+/// Upvoted pages from every source, most recently read first.
+pub fn get_upvoted_pages(conn: &mut SqliteConnection, limit: i64) -> Vec<Page> {
+    use crate::schema::pages::dsl::*;
+
+    pages
+        .filter(vote.gt(0))
+        .select(Page::as_select())
+        .order((read.desc(), date.desc()))
+        .limit(limit)
+        .get_results(conn)
+        .expect("Error loading upvoted pages")
+}
+
+/// Pages of a source that have been voted on, most recently read first.
+pub fn get_voted_pages(conn: &mut SqliteConnection, i_source_id: i32) -> Vec<Page> {
+    use crate::schema::pages::dsl::*;
+
+    pages
+        .filter(source_id.eq(i_source_id))
+        .filter(vote.ne(0))
+        .select(Page::as_select())
+        .order(read.desc())
+        .get_results(conn)
+        .expect("Error loading voted pages")
+}
+
+/// Append an entry to the read history.
+pub fn record_history(
+    conn: &mut SqliteConnection,
+    page: &Page,
+    action: HistoryAction,
+) -> QueryResult<()> {
+    use crate::schema::history;
+
+    diesel::insert_into(history::table)
+        .values(&NewHistoryEntry {
+            page_id: page.id,
+            source_id: page.source_id,
+            action,
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// History entries with their pages, newest first.
+pub fn get_history(
+    conn: &mut SqliteConnection,
+    since: Option<NaiveDateTime>,
+    i_source_id: Option<i32>,
+    limit: i64,
+) -> Vec<(HistoryEntry, Page)> {
+    use crate::schema::history::dsl::{created, history, id, source_id};
+    use crate::schema::pages;
+
+    let mut query = history
+        .inner_join(pages::table)
+        .select((HistoryEntry::as_select(), Page::as_select()))
+        .order((created.desc(), id.desc()))
+        .limit(limit)
+        .into_boxed();
+    if let Some(since) = since {
+        query = query.filter(created.ge(since));
+    }
+    if let Some(i_source_id) = i_source_id {
+        query = query.filter(source_id.eq(i_source_id));
+    }
+    query.load(conn).expect("Error loading history")
+}
+
+/// Full text search over page titles, urls, summaries and content. Every
+/// word in `query` has to match, FTS5 query syntax is not exposed.
+pub fn search_pages(conn: &mut SqliteConnection, query: &str, limit: i64) -> Vec<SearchResult> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        return Vec::new();
+    }
+    diesel::sql_query(
+        "SELECT pages.id AS page_id, pages.title, pages.url, pages.read, pages.summary, \
+         sources.title AS source_title, bm25(pages_fts) AS rank \
+         FROM pages_fts \
+         INNER JOIN pages ON pages.id = pages_fts.rowid \
+         INNER JOIN sources ON sources.id = pages.source_id \
+         WHERE pages_fts MATCH ? \
+         ORDER BY rank \
+         LIMIT ?",
+    )
+    .bind::<Text, _>(terms.join(" "))
+    .bind::<BigInt, _>(limit)
+    .load(conn)
+    .expect("Error searching pages")
+}
+
+pub fn get_archive(conn: &mut SqliteConnection, i_page_id: i32) -> Option<Archive> {
+    use crate::schema::archives::dsl::*;
+
+    match archives
+        .filter(page_id.eq(i_page_id))
+        .select(Archive::as_select())
+        .first(conn)
+    {
+        Ok(archive) => Some(archive),
+        Err(err) => match err {
+            NotFound => None,
+            _ => panic!("Database error: {}", err),
+        },
+    }
+}
+
+/// Store an archive, replacing any previous copy of the page.
+pub fn save_archive(conn: &mut SqliteConnection, archive: &Archive) {
+    use crate::schema::archives::dsl::*;
+
+    diesel::replace_into(archives)
+        .values(archive)
+        .execute(conn)
+        .expect("Error saving archive");
+}
+
 pub fn read_status_for_source(
     conn: &mut SqliteConnection,
     i_source_id: i32,
-) -> Vec<Option<NaiveDateTime>> {
+) -> QueryResult<Vec<Option<NaiveDateTime>>> {
     use crate::schema::pages::dsl::{pages, read, source_id};
     use crate::schema::sources::dsl::sources;
 
@@ -222,34 +516,111 @@ pub fn read_status_for_source(
         .filter(source_id.eq(i_source_id))
         .select(read)
         .get_results(conn)
-        .expect("Error loading pages for source")
 }
 
-pub fn pages_with_source_weight(conn: &mut SqliteConnection) -> Vec<(i32, i32)> {
-    use crate::schema::pages::dsl::{date, id, pages, read};
+/// Which unread pages to consider for selection.
+#[derive(Debug, Clone, Copy)]
+pub enum PageFilter<'a> {
+    All,
+    Source(i32),
+    Tag(&'a str),
+}
+
+/// Unread pages as selection candidates, oldest first.
+pub fn pages_with_source_weight(
+    conn: &mut SqliteConnection,
+    filter: PageFilter,
+) -> QueryResult<Vec<Candidate>> {
+    use crate::schema::pages::dsl::{date, id, pages, read, source_id};
     use crate::schema::sources::dsl::{sources, weight};
+    use crate::schema::{source_tags, tags};
 
-    pages
+    let last_read: HashMap<i32, Option<NaiveDateTime>> = pages
+        .group_by(source_id)
+        .select((source_id, diesel::dsl::max(read)))
+        .load(conn)?
+        .into_iter()
+        .collect();
+
+    let mut query = pages
         .inner_join(sources)
         .filter(read.is_null())
-        .select((id, weight))
+        .select((id, source_id, weight, date))
         .order(date.asc())
-        .get_results(conn)
-        .expect("Error loading pages with source weight")
+        .into_boxed();
+    match filter {
+        PageFilter::All => (),
+        PageFilter::Source(i_source_id) => query = query.filter(source_id.eq(i_source_id)),
+        PageFilter::Tag(tag) => {
+            let tagged = source_tags::table
+                .inner_join(tags::table)
+                .filter(tags::name.eq(tag.to_string()))
+                .select(source_tags::source_id);
+            query = query.filter(source_id.eq_any(tagged))
+        }
+    }
+    Ok(query
+        .load::<(i32, i32, i32, NaiveDateTime)>(conn)?
+        .into_iter()
+        .map(
+            |(page_id, page_source_id, page_weight, page_date)| Candidate {
+                page_id,
+                source_id: page_source_id,
+                weight: page_weight,
+                date: page_date,
+                source_last_read: last_read.get(&page_source_id).copied().flatten(),
+            },
+        )
+        .collect())
 }
 
 pub fn set_source_weight(
     conn: &mut SqliteConnection,
     source_id: i32,
     i_weight: i32,
+    bounds: &VoteConfig,
 ) -> (i32, String) {
     use crate::schema::sources::dsl::{id, sources, url, weight};
 
-    let source = get_source_by_id(conn, source_id).expect("Source not found");
-    let new_weight = cmp::max(0, source.weight + i_weight);
+    let source = get_source_by_id(conn, source_id)
+        .expect("Error loading source")
+        .expect("Source not found");
+    let new_weight = cmp::min(
+        bounds.max_weight,
+        cmp::max(bounds.min_weight, source.weight + i_weight),
+    );
     diesel::update(sources.filter(id.eq(source_id)))
         .set(weight.eq(new_weight))
         .returning((weight, url))
         .get_result(conn)
         .expect("Error setting source weight.")
 }
+
+/// Record a vote (1 or -1) on a page and nudge its source's weight. Voting
+/// the same way twice on a page only counts once, changing a vote counts
+/// double.
+pub fn vote_page(
+    conn: &mut SqliteConnection,
+    page: &Page,
+    i_vote: i32,
+    bounds: &VoteConfig,
+) -> (i32, String) {
+    use crate::schema::pages::dsl::{id, pages, vote};
+
+    // Read the current vote rather than trusting page, it may be stale
+    let previous: i32 = pages
+        .filter(id.eq(page.id))
+        .select(vote)
+        .first(conn)
+        .expect("Error loading page vote.");
+    diesel::update(page)
+        .set(vote.eq(i_vote))
+        .execute(conn)
+        .expect("Error setting page vote.");
+    set_source_weight(
+        conn,
+        page.source_id,
+        (i_vote - previous) * bounds.step,
+        bounds,
+    )
+}
@@ -1,67 +1,1104 @@
-use crate::crud::mark_page_read;
-use crate::{find_next_page, sync_sources};
-use diesel::SqliteConnection;
+use crate::atom::{FeedKind, atom_feed, feed_pages};
+use crate::auth::Access;
+use crate::config::{AuthConfig, Config};
+use crate::crud::{
+    delete_source, get_archive, get_page_by_id, get_pages, get_source_by_id, get_source_by_url,
+    get_sources, get_tags_for_source, mark_page_read, mark_page_unread, read_status_for_source,
+    record_history, search_pages, vote_page,
+};
+use crate::models::{HistoryAction, Page, Source};
+use crate::selection::SelectionStrategy;
+use crate::workers::panic_message;
+use crate::{
+    AddSourceError, SyncStats, add_source, escape_html, find_next_page, find_next_page_by_tag,
+    sync_sources, teaser,
+};
+use chrono::{NaiveDateTime, Utc};
+use clap::ValueEnum;
 use diesel::r2d2::{ConnectionManager, Pool};
-use http::{Response, StatusCode};
-use log::{error, info};
-use std::io::{BufRead, BufReader, prelude::*};
-use std::net::{TcpListener, TcpStream};
-
-pub fn server(pool: &Pool<ConnectionManager<SqliteConnection>>) {
-    let conn = &mut pool.get().expect("Failed to get connection");
-    let listener = TcpListener::bind("0.0.0.0:8090").unwrap();
-    println!("Listening on http://0.0.0.0:8090");
-
-    for stream in listener.incoming() {
-        let stream = match stream {
-            Ok(stream) => stream,
-            Err(e) => {
-                error!("Failed to accept connection. Https?: {}", e);
-                continue;
-            }
-        };
-        let page = match find_next_page(conn) {
-            Some(page) => {
-                mark_page_read(conn, &page);
-                page
+use diesel::{QueryResult, SqliteConnection};
+use http::{HeaderValue, Response, StatusCode, header};
+use log::{error, info, warn};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::io::{self, BufRead, BufReader, prelude::*};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use url::{Url, form_urlencoded};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Everything a request handler needs, shared by the worker threads.
+struct ServerState<'a> {
+    pool: &'a Pool<ConnectionManager<SqliteConnection>>,
+    strategy: &'a dyn SelectionStrategy,
+    config: &'a Config,
+    /// Prefix of every path, "" when served from the root.
+    base_path: String,
+    reload: Arc<Mutex<ReloadStatus>>,
+    /// Joined on shutdown so a reload isn't cut off halfway.
+    reload_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Where the server listens.
+pub struct ServerOptions {
+    pub bind: String,
+    pub port: u16,
+    /// Path the server is mounted under behind a reverse proxy, e.g. "/mwr".
+    pub base_path: String,
+}
+
+/// Progress of the last background reload, polled through `/reload/status`
+/// and `/api/reload`.
+#[derive(Clone, Default, Serialize)]
+struct ReloadStatus {
+    running: bool,
+    started: Option<NaiveDateTime>,
+    finished: Option<NaiveDateTime>,
+    /// Totals from the last finished reload.
+    stats: Option<SyncStats>,
+    /// Why the last reload stopped early.
+    error: Option<String>,
+}
+
+pub fn server(
+    pool: &Pool<ConnectionManager<SqliteConnection>>,
+    strategy: &dyn SelectionStrategy,
+    config: &Config,
+    options: &ServerOptions,
+) -> io::Result<()> {
+    let base_path = match options.base_path.trim_matches('/') {
+        "" => String::new(),
+        path => format!("/{}", path),
+    };
+    let listener = TcpListener::bind((options.bind.as_str(), options.port))?;
+    let shutdown = Arc::new(AtomicBool::new(false));
+    handle_signals(Arc::clone(&shutdown), listener.local_addr()?)?;
+    println!(
+        "Listening on http://{}:{}{}/",
+        options.bind, options.port, base_path
+    );
+    let state = ServerState {
+        pool,
+        strategy,
+        config,
+        base_path,
+        reload: Arc::new(Mutex::new(ReloadStatus::default())),
+        reload_thread: Mutex::new(None),
+    };
+
+    // One worker per database connection, more would only wait for the pool.
+    // One connection is left for the reload thread.
+    let (sender, receiver) = mpsc::channel::<TcpStream>();
+    let receiver = Mutex::new(receiver);
+    thread::scope(|scope| {
+        for _ in 0..pool.max_size().saturating_sub(1).max(1) {
+            scope.spawn(|| {
+                loop {
+                    let next = receiver.lock().unwrap().recv();
+                    let Ok(stream) = next else {
+                        break;
+                    };
+                    // A panicking handler only drops its own connection
+                    if panic::catch_unwind(AssertUnwindSafe(|| handle_connection(&state, stream)))
+                        .is_err()
+                    {
+                        error!("Request handler panicked");
+                    }
+                }
+            });
+        }
+
+        for stream in listener.incoming() {
+            if shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("Failed to accept connection. Https?: {}", e);
+                    continue;
+                }
+            };
+            // Don't let an idle client hold a worker forever
+            if let Err(err) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+                error!("Failed to set read timeout: {}", err);
             }
-            None => {
-                error!("Failed to select page");
-                continue;
+            if sender.send(stream).is_err() {
+                break;
             }
-        };
-        handle_connection(pool, stream, page.url);
+        }
+        // Workers finish the requests already accepted, then the scope ends
+        drop(sender);
+    });
+
+    let reload_thread = state.reload_thread.lock().unwrap().take();
+    if let Some(reload_thread) = reload_thread {
+        if !reload_thread.is_finished() {
+            eprintln!("Waiting for reload to finish...");
+        }
+        let _ = reload_thread.join();
     }
+    info!("Server stopped");
+    eprintln!("Server stopped.");
+    Ok(())
 }
 
-fn handle_connection(
-    pool: &Pool<ConnectionManager<SqliteConnection>>,
-    mut stream: TcpStream,
-    redirect: String,
-) {
-    info!("Handling http request.");
-    let buf_reader = BufReader::new(&stream);
-    let http_request: Vec<_> = buf_reader
+/// Stop accepting connections on SIGINT or SIGTERM. The accept loop is
+/// blocked, so it is woken by connecting to it. A second signal exits
+/// without waiting.
+fn handle_signals(shutdown: Arc<AtomicBool>, addr: SocketAddr) -> io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    let wake_addr = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::from((Ipv4Addr::LOCALHOST, addr.port()))
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::from((Ipv6Addr::LOCALHOST, addr.port()))
+        }
+        _ => addr,
+    };
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if shutdown.swap(true, Ordering::SeqCst) {
+                eprintln!("Exiting without waiting.");
+                log::logger().flush();
+                process::exit(128 + signal);
+            }
+            info!("Received signal {}, shutting down", signal);
+            eprintln!("Shutting down...");
+            if let Err(err) = TcpStream::connect(wake_addr) {
+                error!("Failed to wake server: {}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Start syncing sources on a new thread unless a reload is already running.
+fn start_reload(state: &ServerState) -> ReloadStatus {
+    let mut status = state.reload.lock().unwrap();
+    if status.running {
+        return status.clone();
+    }
+    *status = ReloadStatus {
+        running: true,
+        started: Some(Utc::now().naive_utc()),
+        finished: None,
+        stats: None,
+        error: None,
+    };
+    let pool = state.pool.clone();
+    let sync_config = state.config.sync.clone();
+    let reload = Arc::clone(&state.reload);
+    let handle = thread::spawn(move || {
+        info!("Reloading sources...");
+        // Catch a panic so the status doesn't say running forever
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            sync_sources(&pool, &sync_config, false, |_, _, _| {})
+        }));
+        let mut status = reload.lock().unwrap();
+        status.running = false;
+        status.finished = Some(Utc::now().naive_utc());
+        match result {
+            Ok(report) => {
+                info!("Reloaded sources: {}", report.totals);
+                status.stats = Some(report.totals);
+            }
+            Err(payload) => {
+                let message = panic_message(payload.as_ref());
+                error!("Reload failed: {}", message);
+                status.error = Some(message);
+            }
+        }
+    });
+    *state.reload_thread.lock().unwrap() = Some(handle);
+    status.clone()
+}
+
+fn reload_status(state: &ServerState) -> ReloadStatus {
+    state.reload.lock().unwrap().clone()
+}
+
+/// Human readable reload progress. Refreshes itself until the reload is done.
+fn reload_response(base: &str, status: StatusCode, reload: &ReloadStatus) -> Response<String> {
+    let body = if reload.running {
+        format!(
+            "<meta http-equiv=\"refresh\" content=\"2; url={}/reload/status\">\
+             <p>Fetching new pages...</p>",
+            base
+        )
+    } else {
+        match (reload.finished, &reload.error, &reload.stats) {
+            (Some(finished), Some(error), _) => format!(
+                "<p>Reload failed at {}: {}</p>",
+                finished.format("%Y-%m-%d %H:%M:%S"),
+                escape_html(error)
+            ),
+            (Some(finished), None, Some(stats)) => format!(
+                "<p>Saved {} at {}.</p>",
+                stats,
+                finished.format("%Y-%m-%d %H:%M:%S")
+            ),
+            _ => "<p>No reload has run yet.</p>".to_string(),
+        }
+    };
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("Location", format!("{}/reload/status", base))
+        .body(html_page(
+            "Reload",
+            &format!("{}<p><a href=\"{}/\">Next page</a></p>", body, base),
+        ))
+        .unwrap()
+}
+
+/// Largest request body the server will read.
+const MAX_BODY: usize = 1024 * 1024;
+
+struct Request {
+    method: String,
+    /// Percent-decoded path segments, `/` is a single empty segment.
+    segments: Vec<String>,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+enum RequestError {
+    /// The client closed the connection without sending a request.
+    Closed,
+    Invalid(StatusCode),
+}
+
+impl Request {
+    fn param(&self, name: &str) -> Option<String> {
+        form_urlencoded::parse(self.query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.to_string())
+    }
+
+    /// A field of a form encoded body.
+    fn field(&self, name: &str) -> Option<String> {
+        form_urlencoded::parse(&self.body)
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.to_string())
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn read_request(stream: &TcpStream, base_path: &str) -> Result<Request, RequestError> {
+    let mut buf_reader = BufReader::new(stream);
+    let mut lines = buf_reader
+        .by_ref()
         .lines()
-        .map(|result| result.unwrap_or_default())
+        .map(|result| result.unwrap_or_default());
+    // Request line is "GET /path?query HTTP/1.1"
+    let request_line = lines.next().unwrap_or_default();
+    if request_line.is_empty() {
+        return Err(RequestError::Closed);
+    }
+    let headers: Vec<(String, String)> = lines
         .take_while(|line| !line.is_empty())
+        .filter_map(|line| {
+            line.split_once(':')
+                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        })
         .collect();
-    if !http_request.is_empty() && http_request[0].contains("reload") {
-        info!("Reloading sources...");
-        // If the path contains "reload", do a sync
-        let count = sync_sources(pool);
-        info!("Reloaded {} sources", count);
+    let [method, target, version] = request_line.split_whitespace().collect::<Vec<_>>()[..] else {
+        return Err(RequestError::Invalid(StatusCode::BAD_REQUEST));
+    };
+    if !version.starts_with("HTTP/") || !target.starts_with('/') {
+        return Err(RequestError::Invalid(StatusCode::BAD_REQUEST));
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    // Proxies may or may not strip the base path before forwarding
+    let path = match path.strip_prefix(base_path) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => path,
+    };
+    let segments = path
+        .trim_matches('/')
+        .split('/')
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect();
+
+    let mut request = Request {
+        method: method.to_string(),
+        segments,
+        query: query.to_string(),
+        headers,
+        body: Vec::new(),
+    };
+    let content_length = match request.header("Content-Length") {
+        Some(length) => length
+            .parse()
+            .map_err(|_| RequestError::Invalid(StatusCode::BAD_REQUEST))?,
+        None => 0,
+    };
+    if content_length > MAX_BODY {
+        return Err(RequestError::Invalid(StatusCode::PAYLOAD_TOO_LARGE));
     }
+    request.body = vec![0; content_length];
+    buf_reader
+        .read_exact(&mut request.body)
+        .map_err(|_| RequestError::Invalid(StatusCode::BAD_REQUEST))?;
+    Ok(request)
+}
+
+fn handle_connection(state: &ServerState, mut stream: TcpStream) {
+    let response = match read_request(&stream, &state.base_path) {
+        Ok(request) => {
+            info!("{} /{}", request.method, request.segments.join("/"));
+            match state.pool.get() {
+                Ok(mut conn) => route(state, &mut conn, &request),
+                Err(err) => {
+                    error!("Failed to get connection: {}", err);
+                    Response::builder()
+                        .status(StatusCode::SERVICE_UNAVAILABLE)
+                        .body("Database busy, try again.".to_string())
+                        .unwrap()
+                }
+            }
+        }
+        Err(RequestError::Closed) => return,
+        Err(RequestError::Invalid(status)) => {
+            info!("Rejected request: {}", status);
+            Response::builder()
+                .status(status)
+                .body(status.canonical_reason().unwrap_or_default().to_string())
+                .unwrap()
+        }
+    };
+    let written = serialize_response_to_bytes(&response)
+        .and_then(|serialized| stream.write_all(&serialized))
+        .and_then(|_| stream.flush());
+    if let Err(err) = written {
+        error!("Failed to write response: {}", err);
+    }
+}
+
+/// Pick the handler for a request. Only `/random` and `/tag/<tag>` select
+/// a page and mark it read without showing it first.
+fn route(state: &ServerState, conn: &mut SqliteConnection, request: &Request) -> Response<String> {
+    let strategy = state.strategy;
+    let base = state.base_path.as_str();
+    let segments: Vec<&str> = request.segments.iter().map(String::as_str).collect();
+    let auth = &state.config.auth;
+    let access = route_access(&request.method, &segments);
+    let api = segments.first() == Some(&"api");
+    if auth.required(access) && !auth.authorized(request.header("Authorization")) {
+        warn!(
+            "Unauthorized {} /{}",
+            request.method,
+            request.segments.join("/")
+        );
+        return unauthorized(auth, api);
+    }
+    if access == Access::Write && !api && is_cross_site(request) {
+        warn!(
+            "Refused cross-site {} /{}",
+            request.method,
+            request.segments.join("/")
+        );
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Cross-site request refused.".to_string())
+            .unwrap();
+    }
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", [""]) => next_response(conn, strategy, base, request.param("tag")),
+        ("GET", ["random"]) => redirect_response(conn, strategy, None),
+        ("GET", ["tag", tag]) => redirect_response(conn, strategy, Some(tag)),
+        ("GET" | "POST", ["reload"]) => {
+            reload_response(base, StatusCode::ACCEPTED, &start_reload(state))
+        }
+        ("GET", ["reload", "status"]) => {
+            reload_response(base, StatusCode::OK, &reload_status(state))
+        }
+        ("GET", ["page", id]) => card_response(conn, base, id, request.param("tag")),
+        ("GET", ["open", id]) => open_response(conn, base, id),
+        ("POST", ["page", id, action]) => {
+            action_response(conn, state.config, base, id, action, request)
+        }
+        ("POST", ["source", id, "delete"]) => delete_source_response(conn, base, id, request),
+        ("GET", ["search"]) => search_response(conn, base, &request.query),
+        ("GET", ["archive", id]) => archive_response(conn, base, id),
+        ("GET", ["feed.xml"]) => feed_response(state, conn, request),
+        (_, ["api", api @ ..]) => api_response(state, conn, request, api),
+        (
+            _,
+            [""]
+            | ["random"]
+            | ["tag", _]
+            | ["reload"]
+            | ["reload", "status"]
+            | ["page", _]
+            | ["open", _]
+            | ["page", _, _]
+            | ["source", _, "delete"]
+            | ["search"]
+            | ["archive", _]
+            | ["feed.xml"],
+        ) => Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body("Method not allowed.".to_string())
+            .unwrap(),
+        _ => not_found(base, "Nothing here."),
+    }
+}
+
+/// Anything that isn't a GET changes something, and so do the GETs that
+/// mark a page read or start a reload.
+fn route_access(method: &str, segments: &[&str]) -> Access {
+    match (method, segments) {
+        ("GET", ["random"] | ["tag", _] | ["open", _] | ["reload"]) => Access::Write,
+        ("GET", _) => Access::Read,
+        _ => Access::Write,
+    }
+}
+
+/// True if a browser sent the request on behalf of another site. Browsers
+/// send cached Basic credentials along, so another page could otherwise vote,
+/// delete or reload with a form or an image. Requests without any of these
+/// headers, like from curl or a bookmark, are fine.
+fn is_cross_site(request: &Request) -> bool {
+    if let Some(site) = request.header("Sec-Fetch-Site") {
+        return !matches!(site.trim(), "same-origin" | "none");
+    }
+    let Some(origin) = request
+        .header("Origin")
+        .or_else(|| request.header("Referer"))
+    else {
+        return false;
+    };
+    let authority = Url::parse(origin).ok().and_then(|url| {
+        let host = url.host_str()?.to_string();
+        Some(match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host,
+        })
+    });
+    match (authority, request.header("Host")) {
+        (Some(authority), Some(host)) => !authority.eq_ignore_ascii_case(host.trim()),
+        _ => true,
+    }
+}
+
+fn unauthorized(auth: &AuthConfig, api: bool) -> Response<String> {
+    // Ask browsers for a password, scripts are expected to send a token
+    let challenge = if auth.password_hash.is_some() {
+        "Basic realm=\"mwr\""
+    } else {
+        "Bearer realm=\"mwr\""
+    };
+    let mut response = if api {
+        json_error(StatusCode::UNAUTHORIZED, "Authentication required")
+    } else {
+        Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body("Authentication required.".to_string())
+            .unwrap()
+    };
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_static(challenge),
+    );
+    response
+}
 
-    let response: Response<String> = Response::builder()
+/// Body of every API error response.
+#[derive(Serialize)]
+struct ApiError {
+    error: String,
+}
+
+/// A source as returned by the API, with the details `mwr list` prints.
+#[derive(Serialize)]
+struct ApiSource {
+    #[serde(flatten)]
+    source: Source,
+    tags: Vec<String>,
+    unread: usize,
+}
+
+#[derive(Deserialize)]
+struct NewApiSource {
+    url: String,
+    title: Option<String>,
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<String> {
+    match serde_json::to_string(value) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .unwrap(),
+        Err(err) => {
+            error!("Failed to serialize response: {}", err);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(String::new())
+                .unwrap()
+        }
+    }
+}
+
+fn json_error(status: StatusCode, message: &str) -> Response<String> {
+    json_response(
+        status,
+        &ApiError {
+            error: message.to_string(),
+        },
+    )
+}
+
+fn api_source(conn: &mut SqliteConnection, source: Source) -> QueryResult<ApiSource> {
+    let unread = read_status_for_source(conn, source.id)?
+        .iter()
+        .filter(|read| read.is_none())
+        .count();
+    Ok(ApiSource {
+        tags: get_tags_for_source(conn, source.id)?,
+        unread,
+        source,
+    })
+}
+
+/// Route `/api/...` requests, `segments` is the path after `api`. Database
+/// errors are answered with a JSON error like any other.
+fn api_response(
+    state: &ServerState,
+    conn: &mut SqliteConnection,
+    request: &Request,
+    segments: &[&str],
+) -> Response<String> {
+    match api_result(state, conn, request, segments) {
+        Ok(response) => response,
+        Err(err) => {
+            error!(
+                "Database error in {} {:?}: {}",
+                request.method, segments, err
+            );
+            json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Database error: {}", err),
+            )
+        }
+    }
+}
+
+fn api_result(
+    state: &ServerState,
+    conn: &mut SqliteConnection,
+    request: &Request,
+    segments: &[&str],
+) -> QueryResult<Response<String>> {
+    let strategy = state.strategy;
+    Ok(match (request.method.as_str(), segments) {
+        ("GET", ["sources"]) => {
+            let sources = get_sources(conn)?
+                .into_iter()
+                .map(|source| api_source(conn, source))
+                .collect::<QueryResult<Vec<ApiSource>>>()?;
+            json_response(StatusCode::OK, &sources)
+        }
+        ("POST", ["sources"]) => {
+            let new_source: NewApiSource = match serde_json::from_slice(&request.body) {
+                Ok(new_source) => new_source,
+                Err(err) => return Ok(json_error(StatusCode::BAD_REQUEST, &err.to_string())),
+            };
+            if let Err(err) = Url::parse(&new_source.url) {
+                return Ok(json_error(
+                    StatusCode::BAD_REQUEST,
+                    &format!("Invalid URL: {}", err),
+                ));
+            }
+            if get_source_by_url(conn, &new_source.url)?.is_some() {
+                return Ok(json_error(StatusCode::CONFLICT, "Source already exists"));
+            }
+            match add_source(conn, &new_source.url, new_source.title, &state.config.sync) {
+                Ok(source) => json_response(StatusCode::CREATED, &api_source(conn, source)?),
+                Err(AddSourceError::Network(err)) => {
+                    json_error(StatusCode::BAD_GATEWAY, &err.to_string())
+                }
+                Err(AddSourceError::Database(err)) => return Err(err),
+            }
+        }
+        ("GET", ["sources", id]) => {
+            let source = match id.parse() {
+                Ok(id) => get_source_by_id(conn, id)?,
+                Err(_) => None,
+            };
+            match source {
+                Some(source) => json_response(StatusCode::OK, &api_source(conn, source)?),
+                None => json_error(StatusCode::NOT_FOUND, "Source not found"),
+            }
+        }
+        ("DELETE", ["sources", id]) => {
+            let Ok(id) = id.parse() else {
+                return Ok(json_error(StatusCode::BAD_REQUEST, "Invalid source ID"));
+            };
+            match delete_source(conn, id)? {
+                Some(_) => Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(String::new())
+                    .unwrap(),
+                None => json_error(StatusCode::NOT_FOUND, "Source not found"),
+            }
+        }
+        ("GET", ["pages"]) => {
+            let unread = match request.param("unread").as_deref() {
+                None | Some("false") | Some("0") => false,
+                Some("true") | Some("1") => true,
+                Some(_) => {
+                    return Ok(json_error(
+                        StatusCode::BAD_REQUEST,
+                        "unread must be true or false",
+                    ));
+                }
+            };
+            let source = match request.param("source").map(|source| source.parse()) {
+                None => None,
+                Some(Ok(source)) => Some(source),
+                Some(Err(_)) => {
+                    return Ok(json_error(StatusCode::BAD_REQUEST, "Invalid source ID"));
+                }
+            };
+            json_response(StatusCode::OK, &get_pages(conn, unread, source)?)
+        }
+        ("GET", ["pages", id]) => match find_page(conn, id)? {
+            Some(page) => json_response(StatusCode::OK, &page),
+            None => json_error(StatusCode::NOT_FOUND, "Page not found"),
+        },
+        ("POST", ["pages", id, "read"]) => match find_page(conn, id)? {
+            Some(page) => {
+                let page = mark_page_read(conn, &page)?;
+                record_history(conn, &page, HistoryAction::Opened)?;
+                json_response(StatusCode::OK, &page)
+            }
+            None => json_error(StatusCode::NOT_FOUND, "Page not found"),
+        },
+        ("POST", ["reload"]) => json_response(StatusCode::ACCEPTED, &start_reload(state)),
+        ("GET", ["reload"]) => json_response(StatusCode::OK, &reload_status(state)),
+        ("POST", ["next"]) => {
+            let next_page = match request.param("tag") {
+                Some(tag) => find_next_page_by_tag(conn, strategy, &tag)?,
+                None => find_next_page(conn, strategy)?,
+            };
+            match next_page {
+                Some(page) => {
+                    let page = mark_page_read(conn, &page)?;
+                    record_history(conn, &page, HistoryAction::Opened)?;
+                    json_response(StatusCode::OK, &page)
+                }
+                None => json_error(StatusCode::NOT_FOUND, "No unread pages found"),
+            }
+        }
+        (
+            _,
+            ["sources"]
+            | ["sources", _]
+            | ["pages"]
+            | ["pages", _]
+            | ["pages", _, "read"]
+            | ["next"]
+            | ["reload"],
+        ) => json_error(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+        _ => json_error(StatusCode::NOT_FOUND, "Not found"),
+    })
+}
+
+/// Select a page, mark it read and redirect to it.
+fn redirect_response(
+    conn: &mut SqliteConnection,
+    strategy: &dyn SelectionStrategy,
+    tag: Option<&str>,
+) -> Response<String> {
+    let next_page = match tag {
+        Some(tag) => find_next_page_by_tag(conn, strategy, tag),
+        None => find_next_page(conn, strategy),
+    };
+    match next_page.expect("Error selecting page") {
+        Some(page) => {
+            mark_page_read(conn, &page).expect("Error setting page read");
+            record_history(conn, &page, HistoryAction::Opened).expect("Error recording history");
+            Response::builder()
+                .status(StatusCode::TEMPORARY_REDIRECT)
+                .header("Location", page.url)
+                .body(String::new())
+                .unwrap()
+        }
+        None => {
+            error!("Failed to select page");
+            Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("No unread pages found.".to_string())
+                .unwrap()
+        }
+    }
+}
+
+/// Query string that keeps the web interface within a tag.
+fn tag_query(tag: Option<&str>) -> String {
+    match tag {
+        Some(tag) => format!(
+            "?{}",
+            form_urlencoded::Serializer::new(String::new())
+                .append_pair("tag", tag)
+                .finish()
+        ),
+        None => String::new(),
+    }
+}
+
+fn see_other(location: &str) -> Response<String> {
+    Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header("Location", location)
+        .body(String::new())
+        .unwrap()
+}
+
+fn not_found(base: &str, message: &str) -> Response<String> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(html_page(
+            "Not found",
+            &format!(
+                "<p>{}</p><p><a href=\"{}/\">Home</a></p>",
+                escape_html(message),
+                base
+            ),
+        ))
+        .unwrap()
+}
+
+fn html_response(title: &str, body: &str) -> Response<String> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(html_page(title, body))
+        .unwrap()
+}
+
+fn html_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width\"><title>{}</title>\
+         <style>body{{font-family:sans-serif;max-width:40em;margin:1em auto;padding:0 1em}}\
+         form{{display:inline}}button,.button{{font-size:1.1em;margin:.2em;padding:.4em .8em}}\
+         </style></head>\n<body>{}</body></html>",
+        escape_html(title),
+        body
+    )
+}
+
+/// Select the next page and send the browser to its card.
+fn next_response(
+    conn: &mut SqliteConnection,
+    strategy: &dyn SelectionStrategy,
+    base: &str,
+    tag: Option<String>,
+) -> Response<String> {
+    let next_page = match tag.as_deref() {
+        Some(tag) => find_next_page_by_tag(conn, strategy, tag),
+        None => find_next_page(conn, strategy),
+    };
+    match next_page.expect("Error selecting page") {
+        Some(page) => see_other(&format!(
+            "{}/page/{}{}",
+            base,
+            page.id,
+            tag_query(tag.as_deref())
+        )),
+        None => html_response(
+            "My Web Ring",
+            &format!(
+                "<p>No unread pages found.</p><p><a href=\"{}/search\">Search</a></p>",
+                base
+            ),
+        ),
+    }
+}
+
+/// The page with the ID from the path, None if it isn't a valid ID.
+fn find_page(conn: &mut SqliteConnection, page_id: &str) -> QueryResult<Option<Page>> {
+    match page_id.parse() {
+        Ok(page_id) => get_page_by_id(conn, page_id),
+        Err(_) => Ok(None),
+    }
+}
+
+/// A card describing a page with buttons for the same actions as `ui_loop`.
+fn card_response(
+    conn: &mut SqliteConnection,
+    base: &str,
+    page_id: &str,
+    tag: Option<String>,
+) -> Response<String> {
+    let Some(page) = find_page(conn, page_id).expect("Error loading page") else {
+        return not_found(base, "No page with that ID found.");
+    };
+    let source_title = get_source_by_id(conn, page.source_id)
+        .expect("Error loading source")
+        .map(|source| source.title)
+        .unwrap_or_default();
+    let teaser = teaser(page.summary.as_deref(), page.content.as_deref(), 300)
+        .map(|teaser| format!("<p>{}</p>", escape_html(&teaser)))
+        .unwrap_or_default();
+    let status = match (page.read.is_some(), page.vote) {
+        (_, 1) => " - 👍",
+        (_, -1) => " - 👎",
+        (true, _) => " - read",
+        (false, _) => "",
+    };
+    let tag_field = match &tag {
+        Some(tag) => format!(
+            "<input type=\"hidden\" name=\"tag\" value=\"{}\">",
+            escape_html(tag)
+        ),
+        None => String::new(),
+    };
+    let button = |action: &str, label: &str| {
+        format!(
+            "<form method=\"post\" action=\"{}/page/{}/{}\">{}<button>{}</button></form>",
+            base, page.id, action, tag_field, label
+        )
+    };
+    let body = format!(
+        "<h2>{title}</h2>\n<p><small>{source} - {date}{status}</small></p>\n{teaser}\n<p>\
+         <a class=\"button\" href=\"{base}/open/{id}\">Open</a>{next}{upvote}{downvote}{unread}</p>\n\
+         <p><form method=\"post\" action=\"{base}/source/{source_id}/delete\" \
+         onsubmit=\"return confirm('Delete this source and all of its pages?')\">{tag_field}\
+         <button>Delete source</button></form> <a href=\"{base}/search\">Search</a></p>",
+        title = escape_html(&page.title),
+        source = escape_html(&source_title),
+        date = page.date.format("%Y-%m-%d"),
+        id = page.id,
+        source_id = page.source_id,
+        next = button("next", "Next"),
+        upvote = button("upvote", "👍"),
+        downvote = button("downvote", "👎"),
+        unread = button("unread", "Mark unread"),
+    );
+    html_response(&page.title, &body)
+}
+
+/// Mark a page read and send the browser to it.
+fn open_response(conn: &mut SqliteConnection, base: &str, page_id: &str) -> Response<String> {
+    let Some(page) = find_page(conn, page_id).expect("Error loading page") else {
+        return not_found(base, "No page with that ID found.");
+    };
+    mark_page_read(conn, &page).expect("Error setting page read");
+    record_history(conn, &page, HistoryAction::Opened).expect("Error recording history");
+    Response::builder()
         .status(StatusCode::TEMPORARY_REDIRECT)
-        .header("Location", redirect)
+        .header("Location", page.url)
         .body(String::new())
-        .unwrap();
-    let serialized = serialize_response_to_bytes(&response).unwrap();
+        .unwrap()
+}
 
-    stream.write_all(&serialized).unwrap();
-    stream.flush().unwrap();
+/// Handle a button on a page card. Votes stay on the card, the rest move on
+/// to the next page.
+fn action_response(
+    conn: &mut SqliteConnection,
+    config: &Config,
+    base: &str,
+    page_id: &str,
+    action: &str,
+    request: &Request,
+) -> Response<String> {
+    let Some(page) = find_page(conn, page_id).expect("Error loading page") else {
+        return not_found(base, "No page with that ID found.");
+    };
+    let tag = tag_query(request.field("tag").as_deref());
+    match action {
+        "next" => {
+            // The card has been seen, don't offer it again
+            if page.read.is_none() {
+                mark_page_read(conn, &page).expect("Error setting page read");
+            }
+            record_history(conn, &page, HistoryAction::Skip).expect("Error recording history");
+            see_other(&format!("{}/{}", base, tag))
+        }
+        "upvote" => {
+            record_history(conn, &page, HistoryAction::Upvote).expect("Error recording history");
+            vote_page(conn, &page, 1, &config.votes);
+            see_other(&format!("{}/page/{}{}", base, page.id, tag))
+        }
+        "downvote" => {
+            record_history(conn, &page, HistoryAction::Downvote).expect("Error recording history");
+            vote_page(conn, &page, -1, &config.votes);
+            see_other(&format!("{}/page/{}{}", base, page.id, tag))
+        }
+        "unread" => {
+            mark_page_unread(conn, &page);
+            record_history(conn, &page, HistoryAction::Unread).expect("Error recording history");
+            see_other(&format!("{}/{}", base, tag))
+        }
+        _ => not_found(base, "Unknown action."),
+    }
+}
+
+fn delete_source_response(
+    conn: &mut SqliteConnection,
+    base: &str,
+    source_id: &str,
+    request: &Request,
+) -> Response<String> {
+    let deleted = source_id
+        .parse()
+        .map_err(|_| "Invalid source ID".to_string())
+        .and_then(|source_id| {
+            delete_source(conn, source_id)
+                .expect("Error deleting source")
+                .ok_or_else(|| "Source not found".to_string())
+        });
+    match deleted {
+        Ok(url) => {
+            info!("Deleted source {}", url);
+            see_other(&format!(
+                "{}/{}",
+                base,
+                tag_query(request.field("tag").as_deref())
+            ))
+        }
+        Err(message) => not_found(base, &message),
+    }
+}
+
+/// List pages matching the `q` query parameter.
+fn search_response(conn: &mut SqliteConnection, base: &str, query: &str) -> Response<String> {
+    let search = form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == "q")
+        .map(|(_, value)| value.to_string())
+        .unwrap_or_default();
+    let results = search_pages(conn, &search, 50);
+    let items: String = results
+        .iter()
+        .map(|result| {
+            let teaser = teaser(result.summary.as_deref(), None, 200).unwrap_or_default();
+            let archived = match get_archive(conn, result.page_id) {
+                Some(_) => format!(
+                    " - <a href=\"{}/archive/{}\">archived</a>",
+                    base, result.page_id
+                ),
+                None => String::new(),
+            };
+            format!(
+                "<li><a href=\"{}/page/{}\">{}</a> <small>{}{}{}</small><br>{}</li>\n",
+                base,
+                result.page_id,
+                escape_html(&result.title),
+                escape_html(&result.source_title),
+                if result.read.is_some() { " - read" } else { "" },
+                archived,
+                escape_html(&teaser)
+            )
+        })
+        .collect();
+    let body = format!(
+        "<form action=\"{}/search\"><input name=\"q\" value=\"{}\"> \
+         <button>Search</button></form>\n<ol>\n{}</ol>",
+        base,
+        escape_html(&search),
+        items
+    );
+    html_response("Search", &body)
+}
+
+/// Serve the archived copy of a page, for when the original is gone.
+fn archive_response(conn: &mut SqliteConnection, base: &str, page_id: &str) -> Response<String> {
+    let archive = page_id
+        .parse()
+        .ok()
+        .and_then(|page_id| get_archive(conn, page_id));
+    let Some(archive) = archive else {
+        return not_found(base, "No archive for that page.");
+    };
+    let url = get_page_by_id(conn, archive.page_id)
+        .expect("Error loading page")
+        .map(|page| page.url)
+        .unwrap_or_default();
+    let body = format!(
+        "<h1>{title}</h1><p><a href=\"{url}\">Original</a> - archived {fetched}</p>\n{html}",
+        title = escape_html(&archive.title),
+        url = escape_html(&url),
+        fetched = archive.fetched.format("%Y-%m-%d"),
+        html = archive.html
+    );
+    html_response(&archive.title, &body)
+}
+
+/// Largest `count` accepted by `/feed.xml`.
+const MAX_FEED_ENTRIES: usize = 200;
+
+/// Atom feed of picked or upvoted pages, `kind` and `count` override the
+/// config file.
+fn feed_response(
+    state: &ServerState,
+    conn: &mut SqliteConnection,
+    request: &Request,
+) -> Response<String> {
+    let feed = &state.config.feed;
+    let kind = match request.param("kind") {
+        Some(kind) => match FeedKind::from_str(&kind, true) {
+            Ok(kind) => kind,
+            Err(_) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body("kind must be picks or upvoted.".to_string())
+                    .unwrap();
+            }
+        },
+        None => feed.kind,
+    };
+    let count = request
+        .param("count")
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(feed.count)
+        .min(MAX_FEED_ENTRIES);
+    let self_url = request.header("Host").map(|host| {
+        format!(
+            "{}://{}{}/feed.xml",
+            request.header("X-Forwarded-Proto").unwrap_or("http"),
+            host,
+            state.base_path
+        )
+    });
+
+    let pages = feed_pages(conn, state.strategy, kind, count);
+    match atom_feed(conn, feed, kind, &pages, self_url.as_deref()) {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/atom+xml; charset=utf-8")
+            .body(body)
+            .unwrap(),
+        Err(err) => {
+            error!("Failed to write feed: {}", err);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(String::new())
+                .unwrap()
+        }
+    }
 }
 
 fn serialize_response_to_bytes(response: &Response<String>) -> std::io::Result<Vec<u8>> {
@@ -70,20 +1107,27 @@ fn serialize_response_to_bytes(response: &Response<String>) -> std::io::Result<V
 
     write_status_line(response, &mut buffer)?;
     write_headers(response.headers(), &mut buffer)?;
-    writeln!(&mut buffer)?; // newline
+    // Every response closes the connection, clients rely on the length
+    write!(&mut buffer, "content-length: {}\r\n", response.body().len())?;
+    write!(&mut buffer, "connection: close\r\n\r\n")?;
     write_body(response.body(), &mut buffer);
 
     Ok(buffer)
 }
 
 fn write_status_line(response: &Response<String>, buf: &mut Vec<u8>) -> std::io::Result<()> {
-    writeln!(buf, "HTTP/1.1 {}", response.status())?;
+    write!(buf, "HTTP/1.1 {}\r\n", response.status())?;
     Ok(())
 }
 
 fn write_headers(headers: &http::HeaderMap, buf: &mut Vec<u8>) -> std::io::Result<()> {
     for (name, value) in headers.iter() {
-        writeln!(buf, "{}: {}", name.as_str(), value.to_str().unwrap_or(""))?;
+        write!(
+            buf,
+            "{}: {}\r\n",
+            name.as_str(),
+            value.to_str().unwrap_or("")
+        )?;
     }
     Ok(())
 }
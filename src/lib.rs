@@ -1,29 +1,49 @@
+pub mod archive;
+pub mod atom;
+pub mod auth;
 pub mod backups;
+pub mod config;
 pub mod crud;
 pub mod http;
 pub mod logger;
 pub mod models;
+pub mod opml;
+pub mod schedule;
 pub mod schema;
+pub mod selection;
+pub mod workers;
 use directories::ProjectDirs;
-use feed_rs::parser;
-use log::{info, warn};
-use std::{fmt, fs, thread};
+use feed_rs::{model::Person, parser};
+use log::{Level, debug, info, log_enabled, warn};
+use std::io::{self, IsTerminal, Write};
+use std::sync::OnceLock;
+use std::time::Instant;
+use std::{fmt, fs};
 use url::Url;
 
-use chrono::{DateTime, NaiveDateTime};
+use archive::fetch_archive;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use config::SyncConfig;
 use crud::{
-    create_or_reset_page, create_pages, create_source, get_page_by_id, get_sources,
-    get_unread_pages_by_source, mark_source_synced, pages_with_source_weight,
-    read_status_for_source,
+    PageFilter, create_or_reset_page, create_pages, create_source, get_page_by_id,
+    get_source_by_id, get_sources, get_tags_for_source, mark_source_synced,
+    pages_with_source_weight, read_status_for_source, record_fetch_failure, record_fetch_success,
+    save_archive, schedule_source,
 };
-use diesel::SqliteConnection;
 use diesel::r2d2::{ConnectionManager, Pool};
-use models::{NewPage, Page, Source, SourceType};
-use rand::random_range;
+use diesel::{QueryResult, SqliteConnection};
+use diesel_migrations::{EmbeddedMigrations, embed_migrations};
+use models::{HistoryEntry, NewPage, Page, SearchResult, Source, SourceType};
+use selection::{Candidate, Score, SelectionStrategy};
+use serde::Serialize;
+use std::collections::HashMap;
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
 pub struct AppDirectories {
     pub database: std::path::PathBuf,
     pub log: std::path::PathBuf,
+    pub config: std::path::PathBuf,
 }
 
 pub fn data_locations() -> AppDirectories {
@@ -34,7 +54,12 @@ pub fn data_locations() -> AppDirectories {
     }
     let database = data_dir.join("mwr.sqlite3");
     let log = data_dir.join("mwr.log");
-    AppDirectories { database, log }
+    let config = path.config_dir().join("config.toml");
+    AppDirectories {
+        database,
+        log,
+        config,
+    }
 }
 
 #[derive(Debug)]
@@ -50,24 +75,92 @@ impl From<ureq::Error> for NetworkError {
     }
 }
 
+impl From<url::ParseError> for NetworkError {
+    fn from(error: url::ParseError) -> Self {
+        NetworkError {
+            message: format!("Invalid URL: {}", error),
+        }
+    }
+}
+
 impl fmt::Display for NetworkError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Network error: {}", self.message)
     }
 }
 
+/// Why `add_source` failed: the source couldn't be fetched, or it couldn't
+/// be stored.
+#[derive(Debug)]
+pub enum AddSourceError {
+    Network(NetworkError),
+    Database(diesel::result::Error),
+}
+
+impl From<ureq::Error> for AddSourceError {
+    fn from(error: ureq::Error) -> Self {
+        AddSourceError::Network(error.into())
+    }
+}
+
+impl From<url::ParseError> for AddSourceError {
+    fn from(error: url::ParseError) -> Self {
+        AddSourceError::Network(error.into())
+    }
+}
+
+impl fmt::Display for AddSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddSourceError::Network(error) => write!(f, "{}", error),
+            AddSourceError::Database(error) => write!(f, "Database error: {}", error),
+        }
+    }
+}
+
 struct HttpResponse {
     body: String,
     last_modified: Option<NaiveDateTime>,
     etag: Option<String>,
+    /// From Cache-Control, how long the server says the feed stays fresh.
+    max_age: Option<Duration>,
+}
+
+/// Longest a single feed or page download may take.
+const DOWNLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Shared agent for downloading feeds and pages, with a timeout so a server
+/// that stops responding can't hold up a sync.
+fn http_agent() -> &'static ureq::Agent {
+    static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+    AGENT.get_or_init(|| {
+        ureq::Agent::config_builder()
+            .timeout_global(Some(DOWNLOAD_TIMEOUT))
+            .build()
+            .into()
+    })
+}
+
+/// Result of a conditional GET of a source.
+enum Download {
+    Modified(HttpResponse),
+    /// 304, nothing changed since the last sync.
+    NotModified {
+        max_age: Option<Duration>,
+    },
+    /// 429 or 503 with a Retry-After header.
+    RetryLater {
+        status: u16,
+        delay: Duration,
+    },
 }
 
 fn download_source(
     url: &str,
     last_modified: &Option<NaiveDateTime>,
     etag: &Option<String>,
-) -> Result<HttpResponse, ureq::Error> {
-    let mut req = ureq::get(url);
+) -> Result<Download, ureq::Error> {
+    let mut req = http_agent().get(url);
     if let Some(last_modified) = last_modified {
         req = req.header("If-Modified-Since", last_modified.and_utc().to_rfc2822());
     }
@@ -75,7 +168,35 @@ fn download_source(
         req = req.header("If-None-Match", etag);
     }
     req = req.header("User-Agent", "MWR Feed Reader");
-    let mut response = req.call()?;
+    // Handle statuses here, 304 isn't an error
+    let mut response = req.config().http_status_as_error(false).build().call()?;
+    let status = response.status();
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|header| header.to_str().ok())
+    };
+    let max_age = header("Cache-Control").and_then(schedule::cache_max_age);
+    if status == ::http::StatusCode::NOT_MODIFIED {
+        return Ok(Download::NotModified { max_age });
+    }
+    if status == ::http::StatusCode::TOO_MANY_REQUESTS
+        || status == ::http::StatusCode::SERVICE_UNAVAILABLE
+    {
+        let now = Utc::now().naive_utc();
+        if let Some(delay) =
+            header("Retry-After").and_then(|value| schedule::retry_after(value, now))
+        {
+            return Ok(Download::RetryLater {
+                status: status.as_u16(),
+                delay,
+            });
+        }
+    }
+    if !status.is_success() {
+        return Err(ureq::Error::StatusCode(status.as_u16()));
+    }
     let body = response.body_mut().read_to_string()?;
 
     let last_modified = response
@@ -91,22 +212,52 @@ fn download_source(
         .and_then(|header| header.to_str().ok())
         .map(|etag| etag.to_string());
 
-    Ok(HttpResponse {
+    Ok(Download::Modified(HttpResponse {
         body,
         last_modified,
         etag,
-    })
+        max_age,
+    }))
 }
 
 struct RssFeed {
     title: String,
     items: Vec<RssItem>,
+    /// How often the feed asks to be checked.
+    refresh: Option<Duration>,
 }
 
 struct RssItem {
     link: String,
     title: String,
     date: Option<NaiveDateTime>,
+    summary: Option<String>,
+    content: Option<String>,
+    authors: Option<String>,
+    categories: Option<String>,
+    media: Option<String>,
+    updated: Option<NaiveDateTime>,
+}
+
+/// Join names with ", ", or None if there aren't any.
+fn join_names<'a>(names: impl Iterator<Item = &'a str>) -> Option<String> {
+    let names: Vec<&str> = names
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+    (!names.is_empty()).then(|| names.join(", "))
+}
+
+/// RSS authors only have an email, conventionally "email (Name)", feed-rs
+/// names them after the element.
+fn author_name(person: &Person) -> &str {
+    match (person.name.as_str(), person.email.as_deref()) {
+        ("author", Some(email)) => email
+            .split_once('(')
+            .and_then(|(_, name)| name.strip_suffix(')'))
+            .unwrap_or(email),
+        (name, _) => name,
+    }
 }
 
 fn parse_rss(body: &str) -> Result<RssFeed, parser::ParseFeedError> {
@@ -115,8 +266,13 @@ fn parse_rss(body: &str) -> Result<RssFeed, parser::ParseFeedError> {
         Some(title) => title.content.clone(),
         None => "Untitled".to_string(),
     };
+    let refresh = feed
+        .ttl
+        .map(|ttl| Duration::minutes(ttl.into()))
+        .or_else(|| schedule::syndication_interval(body));
     Ok(RssFeed {
         title: feed_title,
+        refresh,
         items: feed
             .entries
             .iter()
@@ -131,10 +287,41 @@ fn parse_rss(body: &str) -> Result<RssFeed, parser::ParseFeedError> {
                     None => "Untitled".to_string(),
                 };
 
+                // Youtube and other media feeds put the description on the media
+                let media_description = entry
+                    .media
+                    .iter()
+                    .find_map(|media| media.description.as_ref());
+                let summary = entry
+                    .summary
+                    .as_ref()
+                    .or(media_description)
+                    .map(|summary| summary.content.clone());
+                let media = entry
+                    .media
+                    .iter()
+                    .flat_map(|media| &media.content)
+                    .find_map(|content| content.url.as_ref())
+                    .map(|url| url.to_string());
+
                 RssItem {
                     link,
                     title,
                     date: entry.published.map(|date| date.naive_utc()),
+                    summary,
+                    content: entry
+                        .content
+                        .as_ref()
+                        .and_then(|content| content.body.clone()),
+                    authors: join_names(entry.authors.iter().map(author_name)),
+                    categories: join_names(
+                        entry
+                            .categories
+                            .iter()
+                            .map(|c| c.label.as_deref().unwrap_or(&c.term)),
+                    ),
+                    media,
+                    updated: entry.updated.map(|date| date.naive_utc()),
                 }
             })
             .collect(),
@@ -150,23 +337,127 @@ fn rss_to_newpages(rss_items: Vec<RssItem>, source_id: i32) -> Vec<NewPage> {
             read: None,
             date: item.date,
             source_id,
+            summary: item.summary,
+            content: item.content,
+            authors: item.authors,
+            categories: item.categories,
+            media: item.media,
+            updated: item.updated,
         })
         .collect()
 }
 
+/// The single page of a non-feed website source.
+fn website_page(source: &Source) -> NewPage {
+    NewPage {
+        url: source.url.clone(),
+        title: source.url.clone(),
+        read: None,
+        date: None,
+        source_id: source.id,
+        summary: None,
+        content: None,
+        authors: None,
+        categories: None,
+        media: None,
+        updated: None,
+    }
+}
+
+/// Strip tags from a feed's html and decode the common entities.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                text.push(' ');
+            }
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Escape text for use in HTML, including attribute values.
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A short plain text teaser from the summary, or the content if there is
+/// no summary.
+pub fn teaser(summary: Option<&str>, content: Option<&str>, max_chars: usize) -> Option<String> {
+    let text = html_to_text(summary.or(content)?);
+    if text.is_empty() {
+        return None;
+    }
+    if text.chars().count() <= max_chars {
+        return Some(text);
+    }
+    let truncated: String = text.chars().take(max_chars).collect();
+    Some(format!("{}…", truncated.trim_end()))
+}
+
 pub fn add_source(
     conn: &mut SqliteConnection,
     url: &str,
     title: Option<String>,
-) -> Result<Source, NetworkError> {
-    let parsed_url = Url::parse(url).expect("Invalid URL");
-    let resp = download_source(url, &None::<NaiveDateTime>, &None::<String>)?;
+    config: &SyncConfig,
+) -> Result<Source, AddSourceError> {
+    let parsed_url = Url::parse(url)?;
+    let resp = match download_source(url, &None::<NaiveDateTime>, &None::<String>)? {
+        Download::Modified(resp) => resp,
+        // Without conditional headers the server has no reason to send a 304
+        Download::NotModified { .. } => {
+            return Err(AddSourceError::Network(NetworkError {
+                message: "Unexpected 304 Not Modified".to_string(),
+            }));
+        }
+        Download::RetryLater { status, delay } => {
+            return Err(AddSourceError::Network(NetworkError {
+                message: format!(
+                    "http status: {}, retry in {} seconds",
+                    status,
+                    delay.num_seconds()
+                ),
+            }));
+        }
+    };
+    let db = AddSourceError::Database;
     if let Ok(rss_feed) = parse_rss(&resp.body) {
-        let source = create_source(conn, url, SourceType::Rss, rss_feed.title);
+        let source = create_source(conn, url, SourceType::Rss, rss_feed.title).map_err(db)?;
         let new_pages = rss_to_newpages(rss_feed.items, source.id);
-        let new_pages = create_pages(conn, new_pages);
-        info!("Added {} new pages for source {}", new_pages, source.id);
-        mark_source_synced(conn, &source, resp.last_modified, resp.etag);
+        let new_pages = create_pages(conn, new_pages).map_err(db)?;
+        info!(
+            "Added {} new pages for source {}",
+            new_pages.len(),
+            source.id
+        );
+        mark_source_synced(
+            conn,
+            &source,
+            resp.last_modified,
+            resp.etag,
+            to_minutes(rss_feed.refresh),
+        )
+        .map_err(db)?;
+        let now = Utc::now().naive_utc();
+        let next = schedule::next_fetch(now, &[rss_feed.refresh, resp.max_age], config);
+        schedule_source(conn, source.id, next);
         Ok(source)
     } else {
         warn!("Could not parse RSS, adding single page.");
@@ -175,123 +466,645 @@ pub fn add_source(
             url,
             SourceType::Website,
             title.unwrap_or(parsed_url.host_str().unwrap_or(url).to_string()),
-        );
-        create_or_reset_page(
-            conn,
-            NewPage {
-                url: source.url.clone(),
-                title: source.url.clone(),
-                read: None,
-                date: None,
-                source_id: source.id,
-            },
-        );
+        )
+        .map_err(db)?;
+        create_or_reset_page(conn, website_page(&source)).map_err(db)?;
         Ok(source)
     }
 }
-fn sync_source(conn: &mut SqliteConnection, source: &Source) -> usize {
-    let mut count = 0;
-    match source.s_type {
-        SourceType::Rss => {
-            let resp = match download_source(&source.url, &source.last_modified, &source.etag) {
-                Ok(resp) => resp,
-                Err(err) => {
-                    println!("Failed to download source {}: {}", source.id, err);
-                    return 0;
-                }
-            };
-            if let Ok(rss_feed) = parse_rss(&resp.body) {
-                let new_pages = rss_to_newpages(rss_feed.items, source.id);
-                count += create_pages(conn, new_pages);
-                mark_source_synced(conn, source, resp.last_modified, resp.etag);
+
+/// What happened to a single source during a sync.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "kebab-case")]
+pub enum SyncOutcome {
+    NewPages {
+        count: usize,
+    },
+    /// Fetched and parsed, but nothing new.
+    Unchanged,
+    /// The server answered 304, pages were left alone.
+    NotModified,
+    /// The download failed, including error statuses and rate limiting.
+    HttpError {
+        error: String,
+    },
+    ParseError {
+        error: String,
+    },
+    /// A website source, its page was marked unread again.
+    PageReset,
+    /// Not fetched this time.
+    Skipped {
+        reason: SkipReason,
+    },
+}
+
+/// Why a sync left a source alone.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// Its next fetch time hasn't come yet.
+    NotDue,
+    /// It failed recently and is waiting out its backoff.
+    BackingOff,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::NotDue => write!(f, "not due"),
+            SkipReason::BackingOff => write!(f, "backing off"),
+        }
+    }
+}
+
+impl fmt::Display for SyncOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncOutcome::NewPages { count } => write!(f, "{} new pages", count),
+            SyncOutcome::Unchanged => write!(f, "unchanged"),
+            SyncOutcome::NotModified => write!(f, "not modified"),
+            SyncOutcome::HttpError { error } => write!(f, "{}", error),
+            SyncOutcome::ParseError { error } => write!(f, "{}", error),
+            SyncOutcome::PageReset => write!(f, "page reset"),
+            SyncOutcome::Skipped { reason } => write!(f, "skipped, {}", reason),
+        }
+    }
+}
+
+/// Totals for a sync of all sources.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SyncStats {
+    pub new_pages: usize,
+    /// Sources with new pages.
+    pub updated: usize,
+    pub unchanged: usize,
+    pub not_modified: usize,
+    /// Website sources whose page was marked unread again.
+    pub reset: usize,
+    pub failed: usize,
+    /// Sources left alone because they aren't due yet or are backing off
+    /// after failures.
+    pub skipped: usize,
+}
+
+impl SyncStats {
+    fn add(&mut self, outcome: &SyncOutcome) {
+        match outcome {
+            SyncOutcome::NewPages { count } => {
+                self.updated += 1;
+                self.new_pages += count;
             }
+            SyncOutcome::Unchanged => self.unchanged += 1,
+            SyncOutcome::NotModified => self.not_modified += 1,
+            SyncOutcome::HttpError { .. } | SyncOutcome::ParseError { .. } => self.failed += 1,
+            SyncOutcome::PageReset => self.reset += 1,
+            SyncOutcome::Skipped { .. } => self.skipped += 1,
         }
-        SourceType::Website => {
-            create_or_reset_page(
+    }
+}
+
+impl fmt::Display for SyncStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} new pages ({} updated, {} unchanged, {} not modified, {} reset, {} failed, {} skipped)",
+            self.new_pages,
+            self.updated,
+            self.unchanged,
+            self.not_modified,
+            self.reset,
+            self.failed,
+            self.skipped
+        )
+    }
+}
+
+/// How one source fared in a sync.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceReport {
+    pub source_id: i32,
+    pub url: String,
+    #[serde(flatten)]
+    pub outcome: SyncOutcome,
+    /// Time spent downloading and parsing.
+    pub duration_ms: u64,
+}
+
+/// Everything `sync_sources` did, source by source: the skipped ones first,
+/// then the rest in the order they finished.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SyncReport {
+    pub totals: SyncStats,
+    pub sources: Vec<SourceReport>,
+    pub duration_ms: u64,
+}
+
+fn millis(duration: std::time::Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Whole minutes, for storing a refresh interval.
+fn to_minutes(interval: Option<Duration>) -> Option<i32> {
+    interval.and_then(|interval| i32::try_from(interval.num_minutes()).ok())
+}
+
+/// What fetching a source brought back, before anything is stored.
+enum Fetch {
+    Feed(RssFeed, HttpResponse),
+    NotModified { max_age: Option<Duration> },
+    RetryLater { status: u16, delay: Duration },
+    DownloadFailed(String),
+    ParseFailed(String),
+    Website,
+}
+
+/// Download and parse a source. Runs on a sync worker, so it stays away
+/// from the database.
+fn fetch_source(source: &Source) -> Fetch {
+    if let SourceType::Website = source.s_type {
+        return Fetch::Website;
+    }
+    match download_source(&source.url, &source.last_modified, &source.etag) {
+        Ok(Download::Modified(resp)) => match parse_rss(&resp.body) {
+            Ok(rss_feed) => Fetch::Feed(rss_feed, resp),
+            Err(err) => Fetch::ParseFailed(format!("Invalid feed: {}", err)),
+        },
+        Ok(Download::NotModified { max_age }) => Fetch::NotModified { max_age },
+        Ok(Download::RetryLater { status, delay }) => Fetch::RetryLater { status, delay },
+        Err(err) => Fetch::DownloadFailed(err.to_string()),
+    }
+}
+
+/// Save what `fetch_source` brought back and schedule the next fetch. Pages
+/// that didn't exist yet are added to `created`.
+fn store_fetch(
+    conn: &mut SqliteConnection,
+    source: &Source,
+    fetch: Fetch,
+    config: &SyncConfig,
+    created: &mut Vec<Page>,
+) -> SyncOutcome {
+    let now = Utc::now().naive_utc();
+    match fetch {
+        Fetch::Feed(rss_feed, resp) => {
+            let new_pages = create_pages(conn, rss_to_newpages(rss_feed.items, source.id))
+                .expect("Error creating pages");
+            let count = new_pages.len();
+            created.extend(new_pages);
+            mark_source_synced(
                 conn,
-                NewPage {
-                    url: source.url.clone(),
-                    title: source.url.clone(),
-                    read: None,
-                    date: None,
-                    source_id: source.id,
-                },
+                source,
+                resp.last_modified,
+                resp.etag,
+                to_minutes(rss_feed.refresh),
+            )
+            .expect("Error marking source as synced");
+            record_fetch_success(conn, source.id);
+            let next = schedule::next_fetch(now, &[rss_feed.refresh, resp.max_age], config);
+            schedule_source(conn, source.id, next);
+            info!("Added {} new pages for source {}", count, source.id);
+            if count > 0 {
+                SyncOutcome::NewPages { count }
+            } else {
+                SyncOutcome::Unchanged
+            }
+        }
+        Fetch::NotModified { max_age } => {
+            info!("Source {} not modified", source.id);
+            record_fetch_success(conn, source.id);
+            let refresh = source
+                .refresh_minutes
+                .map(|minutes| Duration::minutes(minutes.into()));
+            schedule_source(
+                conn,
+                source.id,
+                schedule::next_fetch(now, &[refresh, max_age], config),
+            );
+            SyncOutcome::NotModified
+        }
+        Fetch::RetryLater { status, delay } => {
+            warn!(
+                "Source {} is rate limited, retrying in {} seconds",
+                source.id,
+                delay.num_seconds()
             );
+            let error = format!("http status: {}", status);
+            record_fetch_failure(conn, source.id, &error);
+            schedule_source(conn, source.id, schedule::retry_at(now, delay, config));
+            SyncOutcome::HttpError { error }
+        }
+        Fetch::DownloadFailed(error) => {
+            warn!("Failed to download source {}: {}", source.id, error);
+            record_fetch_failure(conn, source.id, &error);
+            SyncOutcome::HttpError { error }
+        }
+        Fetch::ParseFailed(error) => {
+            warn!("Failed to parse source {}: {}", source.id, error);
+            record_fetch_failure(conn, source.id, &error);
+            SyncOutcome::ParseError { error }
         }
+        Fetch::Website => {
+            create_or_reset_page(conn, website_page(source)).expect("Error resetting page");
+            SyncOutcome::PageReset
+        }
+    }
+}
+
+/// How long to leave a source alone after `failures` failed fetches in a row.
+fn backoff(failures: i32, config: &SyncConfig) -> Duration {
+    let max = Duration::hours(config.max_backoff_hours);
+    let doublings = (failures - 1).clamp(0, 30) as u32;
+    Duration::minutes(config.backoff_minutes)
+        .checked_mul(2i32.saturating_pow(doublings))
+        .map_or(max, |delay| delay.min(max))
+}
+
+/// True if a failing source was tried too recently to try again.
+pub fn is_backing_off(source: &Source, now: NaiveDateTime, config: &SyncConfig) -> bool {
+    match source.last_attempt {
+        Some(last_attempt) if source.failures > 0 => {
+            now < last_attempt + backoff(source.failures, config)
+        }
+        _ => false,
     }
-    info!("Added {} new pages for source {}", count, source.id);
-    count
 }
-pub fn sync_sources(pool: &Pool<ConnectionManager<SqliteConnection>>) -> usize {
+
+/// True if the source's next fetch time has come.
+pub fn is_due(source: &Source, now: NaiveDateTime) -> bool {
+    source.next_fetch.is_none_or(|next_fetch| next_fetch <= now)
+}
+
+/// Fetch every source that's due. `force` fetches all of them, ignoring
+/// refresh intervals and backoff. `progress` is called as each source is
+/// done, with how many are done out of how many.
+pub fn sync_sources(
+    pool: &Pool<ConnectionManager<SqliteConnection>>,
+    config: &SyncConfig,
+    force: bool,
+    mut progress: impl FnMut(&SourceReport, usize, usize),
+) -> SyncReport {
+    let started = Instant::now();
     let conn = &mut pool.get().expect("Failed to get connection");
-    let sources = get_sources(conn);
-    let handles: Vec<_> = sources
-        .chunks(5)
-        .map(|chunk| {
-            let chunk_owned = chunk.to_vec();
-            let conn_pool = pool.clone();
-            thread::spawn(move || {
-                let mut conn = conn_pool.get().unwrap();
-                chunk_owned
-                    .into_iter()
-                    .map(|source| sync_source(&mut conn, &source))
-                    .sum::<usize>()
-            })
-        })
-        .collect();
-    handles
-        .into_iter()
-        .map(|handle| handle.join().unwrap_or(0))
-        .sum()
+    let now = Utc::now().naive_utc();
+    let mut report = SyncReport::default();
+    let mut sources = Vec::new();
+    for source in get_sources(conn).expect("Error loading sources") {
+        let reason = if force {
+            None
+        } else if is_backing_off(&source, now, config) {
+            debug!(
+                "Skipping source {} after {} failures",
+                source.id, source.failures
+            );
+            Some(SkipReason::BackingOff)
+        } else if !is_due(&source, now) {
+            debug!("Skipping source {} until it's due", source.id);
+            Some(SkipReason::NotDue)
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => {
+                let outcome = SyncOutcome::Skipped { reason };
+                report.totals.add(&outcome);
+                report.sources.push(SourceReport {
+                    source_id: source.id,
+                    url: source.url,
+                    outcome,
+                    duration_ms: 0,
+                });
+            }
+            None => sources.push(source),
+        }
+    }
+    let total = sources.len();
+    // Downloads run on the workers, only this thread writes to the database
+    let mut created = Vec::new();
+    workers::run_per_host(
+        sources,
+        config.workers,
+        |source| workers::host_of(&source.url),
+        |source| {
+            let fetch_started = Instant::now();
+            (fetch_source(source), fetch_started.elapsed())
+        },
+        |source, result| {
+            let (fetch, duration) = result.unwrap_or_else(|message| {
+                let error = format!("Fetch panicked: {}", message);
+                (Fetch::DownloadFailed(error), std::time::Duration::ZERO)
+            });
+            let outcome = store_fetch(conn, &source, fetch, config, &mut created);
+            report.totals.add(&outcome);
+            report.sources.push(SourceReport {
+                source_id: source.id,
+                url: source.url,
+                outcome,
+                duration_ms: millis(duration),
+            });
+            progress(report.sources.last().unwrap(), report.sources.len(), total);
+        },
+    );
+    // Only pages this sync added, so a page that can't be archived isn't
+    // downloaded again on every sync
+    if config.archive {
+        workers::run_per_host(
+            created,
+            config.workers,
+            |page| workers::host_of(&page.url),
+            fetch_archive,
+            |page, archive| match archive {
+                Ok(Ok(archive)) => {
+                    save_archive(conn, &archive);
+                    info!("Archived page {}", page.id);
+                }
+                Ok(Err(err)) => warn!("Failed to archive page {}: {}", page.id, err),
+                Err(message) => warn!("Archiving page {} panicked: {}", page.id, message),
+            },
+        );
+    }
+    report.duration_ms = millis(started.elapsed());
+    report
+}
+
+/// Longest URL shown in the progress line, so it stays on one line.
+const PROGRESS_URL_WIDTH: usize = 60;
+
+/// Show the last synced source on a single line of stderr, if it's a
+/// terminal. Clear it with `finish_sync_progress` once the sync returns.
+pub fn print_sync_progress(source: &SourceReport, done: usize, total: usize) {
+    let mut stderr = io::stderr();
+    if !stderr.is_terminal() {
+        return;
+    }
+    let url: String = source.url.chars().take(PROGRESS_URL_WIDTH).collect();
+    let _ = write!(
+        stderr,
+        "\r\x1b[2K[{}/{}] {} {}",
+        done, total, url, source.outcome
+    );
+    let _ = stderr.flush();
+}
+
+/// Clear the line left by `print_sync_progress`.
+pub fn finish_sync_progress() {
+    let mut stderr = io::stderr();
+    if stderr.is_terminal() {
+        let _ = write!(stderr, "\r\x1b[2K");
+        let _ = stderr.flush();
+    }
+}
+
+/// Sources that failed, then the totals.
+pub fn print_sync_report(report: &SyncReport) {
+    for source in &report.sources {
+        if let SyncOutcome::HttpError { .. } | SyncOutcome::ParseError { .. } = source.outcome {
+            println!("{:<5}{}: {}", source.source_id, source.url, source.outcome);
+        }
+    }
+    println!(
+        "Saved {} in {:.1}s",
+        report.totals,
+        report.duration_ms as f64 / 1000.0
+    );
 }
 
 pub fn print_source_list(conn: &mut SqliteConnection, sources: &Vec<Source>) {
     println!("{:<5}{:<4}{:<8}Title", "ID", "👍", "Unread");
     for s in sources {
-        let total = read_status_for_source(conn, s.id);
+        let total = read_status_for_source(conn, s.id).expect("Error loading pages for source");
         let unread = total.iter().filter(|read| read.is_none()).count();
+        let tags = get_tags_for_source(conn, s.id).expect("Error loading tags");
+        let tags = if tags.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", tags.join(", "))
+        };
+        let failing = if s.failures > 0 {
+            format!(" (failed {} times in a row)", s.failures)
+        } else {
+            String::new()
+        };
         println!(
-            "{:<5}{:<5}{:<8}{}",
+            "{:<5}{:<5}{:<8}{}{}{}",
             s.id,
             s.weight,
             format!("{}/{}", unread, total.len()),
-            s.title
+            s.title,
+            tags,
+            failing
         );
     }
     println!("{} sources.", sources.len());
 }
 
-pub fn find_next_page_by_source_id(conn: &mut SqliteConnection, source_id: i32) -> Option<Page> {
-    let pages = get_unread_pages_by_source(conn, source_id);
-    // Single source, so we don't care about weight
-    let weighted_pages = pages.into_iter().map(|page| (page.id, 10)).collect();
-    select_page(conn, weighted_pages)
+pub fn print_broken_sources(sources: &[Source]) {
+    println!(
+        "{:<5}{:<6}{:<18}{:<18}Title",
+        "ID", "Fails", "Last attempt", "Last success"
+    );
+    let format_date = |date: Option<NaiveDateTime>| match date {
+        Some(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+        None => "never".to_string(),
+    };
+    for s in sources {
+        println!(
+            "{:<5}{:<6}{:<18}{:<18}{}",
+            s.id,
+            s.failures,
+            format_date(s.last_attempt),
+            format_date(s.last_success),
+            s.title
+        );
+        println!("     {}", s.last_error.as_deref().unwrap_or_default());
+    }
+    println!("{} broken sources.", sources.len());
+}
+
+pub fn print_history(entries: &[(HistoryEntry, Page)]) {
+    println!(
+        "{:<18}{:<10}{:<8}{:<7}Title",
+        "Date", "Action", "Source", "Page"
+    );
+    for (entry, page) in entries {
+        println!(
+            "{:<18}{:<10}{:<8}{:<7}{}",
+            entry.created.format("%Y-%m-%d %H:%M"),
+            entry.action.to_string(),
+            entry.source_id,
+            page.id,
+            page.title
+        );
+    }
+    println!("{} entries.", entries.len());
+}
+
+pub fn print_search_results(results: &[SearchResult]) {
+    println!("{:<7}{:<6}{:<20}Title", "Page", "Read", "Source");
+    for result in results {
+        let source: String = result.source_title.chars().take(18).collect();
+        let read = if result.read.is_some() { "✓" } else { "" };
+        println!(
+            "{:<7}{:<6}{:<20}{}",
+            result.page_id, read, source, result.title
+        );
+        println!("{:<33}{}", "", result.url);
+        if let Some(teaser) = teaser(result.summary.as_deref(), None, 100) {
+            println!("{:<33}{}", "", teaser);
+        }
+    }
+    println!("{} results.", results.len());
+}
+
+pub fn print_votes(source: &Source, pages: &[Page]) {
+    println!("{} (weight {})", source.title, source.weight);
+    println!("{:<6}{:<7}Title", "Vote", "Page");
+    for page in pages {
+        let vote = if page.vote > 0 { "👍" } else { "👎" };
+        println!("{:<5}{:<7}{}", vote, page.id, page.title);
+    }
+    let up = pages.iter().filter(|page| page.vote > 0).count();
+    println!("{} up, {} down.", up, pages.len() - up);
+}
+
+pub fn find_next_page_by_source_id(
+    conn: &mut SqliteConnection,
+    strategy: &dyn SelectionStrategy,
+    source_id: i32,
+) -> QueryResult<Option<Page>> {
+    let candidates = pages_with_source_weight(conn, PageFilter::Source(source_id))?;
+    select_page(conn, strategy, candidates)
 }
 
-pub fn find_next_page(conn: &mut SqliteConnection) -> Option<Page> {
-    let pages = pages_with_source_weight(conn);
-    select_page(conn, pages)
+pub fn find_next_page(
+    conn: &mut SqliteConnection,
+    strategy: &dyn SelectionStrategy,
+) -> QueryResult<Option<Page>> {
+    let candidates = pages_with_source_weight(conn, PageFilter::All)?;
+    select_page(conn, strategy, candidates)
+}
+
+pub fn find_next_page_by_tag(
+    conn: &mut SqliteConnection,
+    strategy: &dyn SelectionStrategy,
+    tag: &str,
+) -> QueryResult<Option<Page>> {
+    let candidates = pages_with_source_weight(conn, PageFilter::Tag(tag))?;
+    select_page(conn, strategy, candidates)
+}
+
+pub struct Explanation {
+    pub page: Page,
+    pub source_title: String,
+    pub score: Score,
+    /// Chance of this page being the next one selected.
+    pub probability: f64,
 }
 
-/// Use cumulative sum method to select a page on weighted probability.
-/// Also weights newer entries slightly higher.
-pub fn select_page(conn: &mut SqliteConnection, weighted_pages: Vec<(i32, i32)>) -> Option<Page> {
-    let mut sum = 0;
-    let cum_sum: Vec<(i32, i32)> = weighted_pages
-        .iter()
-        .enumerate()
-        .map(|(index, (page_id, weight))| {
-            // Weight newer entries slightly higher
-            let adj_weight = weight + (index as i32 / 10);
-            sum += adj_weight;
-            (*page_id, sum)
+/// Score unread pages the same way `select_page` would and return the `limit`
+/// most likely picks, along with the total number of candidates.
+pub fn explain_selection(
+    conn: &mut SqliteConnection,
+    strategy: &dyn SelectionStrategy,
+    filter: PageFilter,
+    limit: usize,
+) -> (Vec<Explanation>, usize) {
+    let candidates = pages_with_source_weight(conn, filter).expect("Error loading pages");
+    let scores = strategy.scores(&candidates, Utc::now().naive_utc());
+    let total: f64 = scores.iter().map(|score| score.total).sum();
+    // Matches the uniform fallback in `SelectionStrategy::select`
+    let uniform = !total.is_finite() || total <= 0.0;
+    let count = candidates.len() as f64;
+    let mut ranked: Vec<(&Candidate, Score)> = candidates.iter().zip(scores).collect();
+    ranked.sort_by(|a, b| b.1.total.total_cmp(&a.1.total));
+
+    let mut source_titles: HashMap<i32, String> = HashMap::new();
+    let explanations = ranked
+        .into_iter()
+        .take(limit)
+        .filter_map(|(candidate, score)| {
+            let page = get_page_by_id(conn, candidate.page_id).expect("Error loading page")?;
+            let source_title = source_titles
+                .entry(candidate.source_id)
+                .or_insert_with(|| {
+                    get_source_by_id(conn, candidate.source_id)
+                        .expect("Error loading source")
+                        .map(|source| source.title)
+                        .unwrap_or_default()
+                })
+                .clone();
+            Some(Explanation {
+                page,
+                source_title,
+                score,
+                probability: if uniform {
+                    1.0 / count
+                } else {
+                    score.total / total
+                },
+            })
         })
         .collect();
-    let pick = random_range(0..sum + 1);
-    for (page_id, weight) in cum_sum {
-        if pick <= weight {
-            return get_page_by_id(conn, page_id);
+    (explanations, candidates.len())
+}
+
+pub fn print_explanation(explanations: &[Explanation], candidates: usize) {
+    println!(
+        "{:<9}{:<8}{:<9}{:<7}{:<20}Title",
+        "Chance", "Weight", "Recency", "Page", "Source"
+    );
+    for e in explanations {
+        let source: String = e.source_title.chars().take(18).collect();
+        println!(
+            "{:<9}{:<8}{:<9}{:<7}{:<20}{}",
+            format!("{:.2}%", e.probability * 100.0),
+            e.score.weight,
+            format!("x{:.2}", e.score.recency),
+            e.page.id,
+            source,
+            e.page.title
+        );
+    }
+    println!("Top {} of {} unread pages.", explanations.len(), candidates);
+}
+
+/// Select up to `count` different unread pages without marking them read.
+pub fn pick_pages(
+    conn: &mut SqliteConnection,
+    strategy: &dyn SelectionStrategy,
+    count: usize,
+) -> Vec<Page> {
+    let mut candidates =
+        pages_with_source_weight(conn, PageFilter::All).expect("Error loading pages");
+    let now = Utc::now().naive_utc();
+    let mut rng = rand::rng();
+    let mut picks = Vec::new();
+    while picks.len() < count {
+        let Some(index) = strategy.select(&candidates, now, &mut rng) else {
+            break;
+        };
+        let candidate = candidates.swap_remove(index);
+        if let Some(page) = get_page_by_id(conn, candidate.page_id).expect("Error loading page") {
+            picks.push(page);
         }
     }
-    None
+    picks
+}
+
+/// Let the strategy pick one of the candidates and load its page.
+pub fn select_page(
+    conn: &mut SqliteConnection,
+    strategy: &dyn SelectionStrategy,
+    candidates: Vec<Candidate>,
+) -> QueryResult<Option<Page>> {
+    let now = Utc::now().naive_utc();
+    let Some(index) = strategy.select(&candidates, now, &mut rand::rng()) else {
+        return Ok(None);
+    };
+    let candidate = &candidates[index];
+    if log_enabled!(Level::Debug) {
+        let score = strategy.scores(&candidates, now)[index];
+        debug!("Selected page {} with {:?}", candidate.page_id, score);
+    }
+    get_page_by_id(conn, candidate.page_id)
 }
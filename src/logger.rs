@@ -5,7 +5,7 @@ use std::{
     io::{BufWriter, Write},
     path::PathBuf,
     sync::mpsc::{self, Receiver, Sender},
-    thread,
+    thread::{self, JoinHandle},
 };
 
 pub struct AsyncFileLogger {
@@ -15,24 +15,43 @@ pub struct AsyncFileLogger {
 
 enum AsyncFileLoggerMessage {
     Log(String),
+    /// Acknowledged once every earlier message has been written.
+    Flush(Sender<()>),
     Shutdown,
 }
 
+/// Returned by `AsyncFileLogger::init`. The logger itself is owned by the
+/// `log` crate and never dropped, so this is the only way to stop it.
+pub struct LoggerHandle {
+    sender: Sender<AsyncFileLoggerMessage>,
+    thread: JoinHandle<()>,
+}
+
+impl LoggerHandle {
+    /// Write out all queued messages and stop the log thread. Anything
+    /// logged afterwards is dropped.
+    pub fn shutdown(self) {
+        let _ = self.sender.send(AsyncFileLoggerMessage::Shutdown);
+        let _ = self.thread.join();
+    }
+}
+
 impl AsyncFileLogger {
-    pub fn init(path: PathBuf, level_filter: LevelFilter) -> Result<(), SetLoggerError> {
+    pub fn init(path: PathBuf, level_filter: LevelFilter) -> Result<LoggerHandle, SetLoggerError> {
         let (sender, receiver) = mpsc::channel();
 
-        thread::spawn(move || {
+        let thread = thread::spawn(move || {
             Self::log_thread_fn(receiver, &path);
         });
 
         let logger = Self {
-            sender,
+            sender: sender.clone(),
             level_filter,
         };
 
         log::set_max_level(level_filter);
-        log::set_boxed_logger(Box::new(logger))
+        log::set_boxed_logger(Box::new(logger))?;
+        Ok(LoggerHandle { sender, thread })
     }
 
     fn log_thread_fn(receiver: Receiver<AsyncFileLoggerMessage>, path: &PathBuf) {
@@ -53,6 +72,9 @@ impl AsyncFileLogger {
                         eprintln!("Failed to flush log file: {}", e);
                     }
                 }
+                AsyncFileLoggerMessage::Flush(ack) => {
+                    let _ = ack.send(());
+                }
                 AsyncFileLoggerMessage::Shutdown => {
                     break;
                 }
@@ -70,13 +92,17 @@ impl log::Log for AsyncFileLogger {
         if self.enabled(record.metadata()) {
             let now = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
             let message = format!("{} - {} - {}", record.level(), now, record.args(),);
-            self.sender
-                .send(AsyncFileLoggerMessage::Log(message))
-                .unwrap();
+            // The log thread is gone after shutdown, there's nowhere left to write
+            let _ = self.sender.send(AsyncFileLoggerMessage::Log(message));
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        let (ack, done) = mpsc::channel();
+        if self.sender.send(AsyncFileLoggerMessage::Flush(ack)).is_ok() {
+            let _ = done.recv();
+        }
+    }
 }
 
 impl Drop for AsyncFileLogger {
@@ -1,25 +1,41 @@
+use chrono::{NaiveDate, NaiveTime};
 use clap::{Parser, Subcommand};
 use diesel::{
     SqliteConnection,
     connection::SimpleConnection,
     r2d2::{ConnectionManager, CustomizeConnection, Error, Pool},
 };
-use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
+use diesel_migrations::MigrationHarness;
 
-use log::{LevelFilter, debug, info};
+use log::{debug, info};
 use mwr::{
-    add_source, data_locations, find_next_page, find_next_page_by_source_id,
-    logger::AsyncFileLogger, print_source_list, sync_sources,
+    MIGRATIONS, add_source,
+    config::{Config, VoteConfig},
+    data_locations, explain_selection, find_next_page, find_next_page_by_source_id,
+    find_next_page_by_tag, finish_sync_progress,
+    logger::AsyncFileLogger,
+    print_broken_sources, print_explanation, print_history, print_search_results,
+    print_source_list, print_sync_progress, print_sync_report, print_votes, sync_sources, teaser,
 };
 use mwr::{
+    archive::{get_or_archive_page, wrap_text},
+    atom::{FeedKind, atom_feed, feed_pages},
+    auth::hash_secret,
     backups::{backup, restore},
     crud::{
-        delete_source, get_source_by_id, get_sources, mark_page_read, mark_page_unread,
-        mark_source_read, set_source_weight,
+        PageFilter, delete_source, get_broken_sources, get_history, get_page_by_id,
+        get_source_by_id, get_sources, get_sources_by_folder, get_sources_by_tag, get_voted_pages,
+        mark_page_read, mark_page_unread, mark_source_read, record_history, search_pages,
+        tag_source, untag_source, vote_page,
     },
-    http::server,
+    http::{ServerOptions, server},
+    models::HistoryAction,
+    opml::{export_opml, import_opml},
+    selection::{SelectionStrategy, Strategy},
 };
-use std::io::{Write, stdin, stdout};
+use std::io::{Read, Write, stdin, stdout};
+use std::path::PathBuf;
+use std::process;
 use std::thread;
 use std::time::Duration;
 
@@ -53,35 +69,144 @@ impl CustomizeConnection<SqliteConnection, Error> for ConnectionOptions {
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// How pages are selected, overrides the config file
+    #[arg(long, global = true, value_enum)]
+    strategy: Option<Strategy>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Select a page and start the CLI interface (default)
-    Run,
+    Run {
+        /// Only select pages from sources with this tag
+        #[arg(long)]
+        tag: Option<String>,
+    },
     /// Select a page from a specific source
     Open { id: i32 },
     /// Fetch new pages
-    Pull,
+    Pull {
+        /// Fetch every source, even ones that aren't due yet
+        #[arg(long)]
+        force: bool,
+        /// Print a report of every source as JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// List all sources
-    List,
+    List {
+        /// Only list sources in this folder
+        #[arg(long)]
+        folder: Option<String>,
+        /// Only list sources with this tag
+        #[arg(long, conflicts_with = "folder")]
+        tag: Option<String>,
+        /// Only list sources that failed to fetch, with the error
+        #[arg(long, conflicts_with_all = ["folder", "tag"])]
+        broken: bool,
+    },
     /// Add a new source
     Add { url: String, title: Option<String> },
+    /// Add tags to a source
+    Tag {
+        id: i32,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from a source
+    Untag {
+        id: i32,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
     /// Mark source as read
     MarkRead { id: i32 },
     /// Delete a source
     Delete { id: i32 },
-    /// Backup sources and pages to stdout.
+    /// Backup sources, pages, tags and history to stdout.
     Backup,
-    /// Restore sources and pages from stdin.
-    Restore,
+    /// Restore sources, pages, tags and history from stdin.
+    Restore {
+        /// Delete existing sources and pages instead of merging
+        #[arg(long)]
+        replace: bool,
+    },
     /// Start the HTTP server
-    Server,
+    Server {
+        /// Address to listen on, e.g. 127.0.0.1 to only allow local connections
+        #[arg(long, default_value = "0.0.0.0")]
+        bind: String,
+        #[arg(long, default_value_t = 8090)]
+        port: u16,
+        /// Path prefix for links and redirects when behind a reverse proxy, e.g. /mwr
+        #[arg(long, default_value = "")]
+        base_path: String,
+    },
+    /// Import sources from an OPML file
+    ImportOpml { file: PathBuf },
+    /// Export sources as OPML to stdout.
+    ExportOpml,
+    /// Write an Atom feed of picked or upvoted pages to stdout
+    ExportFeed {
+        /// Which pages to include, defaults to the config file
+        #[arg(long)]
+        kind: Option<FeedKind>,
+        /// Number of entries, defaults to the config file
+        #[arg(short = 'n', long)]
+        count: Option<usize>,
+    },
+    /// Show recently opened and rated pages
+    History {
+        /// Only show entries from this date on (YYYY-MM-DD)
+        #[arg(long)]
+        since: Option<NaiveDate>,
+        /// Only show entries for this source
+        #[arg(long)]
+        source: Option<i32>,
+        /// Number of entries to show
+        #[arg(short = 'n', long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Hash a password or token read from stdin for the [auth] config section
+    HashPassword,
+    /// Print the reader mode copy of a page, downloading it if needed
+    Read { id: i32 },
+    /// Search page titles, urls, summaries and content
+    Search {
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Number of results to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: i64,
+    },
+    /// Show the votes that moved a source's weight
+    Votes { id: i32 },
+    /// Show the most likely next pages and why
+    Explain {
+        /// Number of pages to show
+        #[arg(short = 'n', long, default_value_t = 10)]
+        limit: usize,
+        /// Only consider pages from this source
+        #[arg(long)]
+        source: Option<i32>,
+        /// Only consider pages from sources with this tag
+        #[arg(long, conflicts_with = "source")]
+        tag: Option<String>,
+    },
 }
 
-fn ui_loop(conn: &mut SqliteConnection) {
+fn ui_loop(
+    conn: &mut SqliteConnection,
+    strategy: &dyn SelectionStrategy,
+    votes: &VoteConfig,
+    tag: Option<&str>,
+) {
     loop {
-        let page = match find_next_page(conn) {
+        let next_page = match tag {
+            Some(tag) => find_next_page_by_tag(conn, strategy, tag),
+            None => find_next_page(conn, strategy),
+        };
+        let page = match next_page.expect("Error selecting page") {
             Some(page) => page,
             None => {
                 println!("No unread pages found.");
@@ -89,28 +214,40 @@ fn ui_loop(conn: &mut SqliteConnection) {
             }
         };
         if webbrowser::open(&page.url).is_ok() {
-            mark_page_read(conn, &page);
+            mark_page_read(conn, &page).expect("Error setting page read");
+            record_history(conn, &page, HistoryAction::Opened).expect("Error recording history");
         } else {
             println!("Failed to open browser");
         }
         println!("\x1B[1m{} (source {})\x1B[0m", page.title, page.source_id);
+        if let Some(teaser) = teaser(page.summary.as_deref(), page.content.as_deref(), 200) {
+            println!("{}", teaser);
+        }
         println!("[n]ext - [u]pvote - [d]ownvote - [r] mark unread - [q]uit");
         print!("> ");
         stdout().flush().unwrap();
         let mut input = String::new();
         stdin().read_line(&mut input).unwrap();
         match input.trim() {
-            "n" => continue,
+            "n" => {
+                record_history(conn, &page, HistoryAction::Skip).expect("Error recording history")
+            }
             "d" => {
-                let (new_weight, url) = set_source_weight(conn, page.source_id, -1);
+                record_history(conn, &page, HistoryAction::Downvote)
+                    .expect("Error recording history");
+                let (new_weight, url) = vote_page(conn, &page, -1, votes);
                 println!("👎 {} ({})", url, new_weight);
             }
             "u" => {
-                let (new_weight, url) = set_source_weight(conn, page.source_id, 1);
+                record_history(conn, &page, HistoryAction::Upvote)
+                    .expect("Error recording history");
+                let (new_weight, url) = vote_page(conn, &page, 1, votes);
                 println!("👍 {} ({})", url, new_weight);
             }
             "r" => {
                 mark_page_unread(conn, &page);
+                record_history(conn, &page, HistoryAction::Unread)
+                    .expect("Error recording history");
                 println!("Page {} marked unread", page.url);
             }
             "q" => break,
@@ -119,11 +256,10 @@ fn ui_loop(conn: &mut SqliteConnection) {
     }
 }
 
-pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
-
 fn main() {
     let proj_paths = data_locations();
-    AsyncFileLogger::init(proj_paths.log, LevelFilter::Info).unwrap();
+    let mut config = Config::load(&proj_paths.config).expect("Failed to load config file");
+    let logger = AsyncFileLogger::init(proj_paths.log, config.log_level).unwrap();
     let database_url = proj_paths.database;
     debug!("Database url: {:?}", database_url);
     let pool = Pool::builder()
@@ -138,61 +274,211 @@ fn main() {
         ))
         .expect("Could not build connection pool");
 
-    let conn = &mut pool.get().expect("Failed to get connection");
+    let mut pooled = pool.get().expect("Failed to get connection");
+    let conn = &mut pooled;
     conn.run_pending_migrations(MIGRATIONS)
         .expect("Failed to run migrations, cannot continue.");
 
     let cli = Cli::parse();
+    if let Some(strategy) = cli.strategy {
+        config.selection.strategy = strategy;
+    }
+    let strategy = config.selection.build();
+    let run_tag = match &cli.command {
+        Some(Commands::Run { tag }) => tag.clone(),
+        _ => None,
+    };
     match cli.command {
-        Some(Commands::List) => {
-            let sources = get_sources(conn);
+        Some(Commands::List { broken: true, .. }) => {
+            print_broken_sources(&get_broken_sources(conn));
+        }
+        Some(Commands::List { folder, tag, .. }) => {
+            let sources = match (folder, tag) {
+                (Some(folder), _) => get_sources_by_folder(conn, &folder),
+                (_, Some(tag)) => get_sources_by_tag(conn, &tag),
+                (None, None) => get_sources(conn).expect("Error loading sources"),
+            };
             print_source_list(conn, &sources);
         }
-        Some(Commands::Add { url, title }) => match add_source(conn, &url, title) {
+        Some(Commands::Add { url, title }) => match add_source(conn, &url, title, &config.sync) {
             Ok(source) => println!("Added source: {}", source.url),
             Err(err) => println!("Failed to add source: {}", err),
         },
-        Some(Commands::Pull) => {
-            let saved = sync_sources(&pool);
-            println!("Saved {} pages", saved);
+        Some(Commands::Pull { force, json }) => {
+            let report = sync_sources(&pool, &config.sync, force, print_sync_progress);
+            finish_sync_progress();
+            if json {
+                match serde_json::to_string_pretty(&report) {
+                    Ok(json) => println!("{}", json),
+                    Err(err) => eprintln!("{}", err),
+                }
+            } else {
+                print_sync_report(&report);
+            }
         }
         Some(Commands::Delete { id }) => {
-            if let Ok(deleted) = delete_source(conn, id) {
+            if let Some(deleted) = delete_source(conn, id).expect("Error deleting source") {
                 println!("Deleted {}", deleted);
             } else {
                 println!("No source with that ID found.");
             }
         }
-        Some(Commands::Run) | None => {
+        Some(Commands::Run { .. }) | None => {
+            let sync_config = config.sync.clone();
             let handle = thread::spawn(move || {
-                let new_pages = sync_sources(&pool);
-                info!("Synced {} new pages", new_pages);
+                let report = sync_sources(&pool, &sync_config, false, |_, _, _| {});
+                info!("Synced {}", report.totals);
             });
-            ui_loop(conn);
+            ui_loop(conn, strategy.as_ref(), &config.votes, run_tag.as_deref());
             handle.join().unwrap();
         }
         Some(Commands::Open { id }) => {
-            let source = get_source_by_id(conn, id).unwrap();
+            let source = get_source_by_id(conn, id)
+                .expect("Error loading source")
+                .unwrap();
             println!("Opening source: {}", source.title);
-            let page = find_next_page_by_source_id(conn, id).unwrap();
+            let page = find_next_page_by_source_id(conn, strategy.as_ref(), id)
+                .expect("Error selecting page")
+                .unwrap();
             if webbrowser::open(&page.url).is_ok() {
-                mark_page_read(conn, &page);
+                mark_page_read(conn, &page).expect("Error setting page read");
+                record_history(conn, &page, HistoryAction::Opened)
+                    .expect("Error recording history");
             } else {
                 println!("Failed to open browser");
             }
         }
+        Some(Commands::Tag { id, tags }) => {
+            let source = get_source_by_id(conn, id)
+                .expect("Error loading source")
+                .expect("No source with that ID found.");
+            for tag in tags {
+                if tag_source(conn, source.id, &tag) {
+                    println!("Tagged {} with {}", source.title, tag);
+                }
+            }
+        }
+        Some(Commands::Untag { id, tags }) => {
+            for tag in tags {
+                if untag_source(conn, id, &tag) {
+                    println!("Removed tag {}", tag);
+                } else {
+                    println!("Source {} is not tagged {}", id, tag);
+                }
+            }
+        }
         Some(Commands::MarkRead { id }) => {
             let pages_marked = mark_source_read(conn, id);
             println!("Marked {} pages as read", pages_marked.len());
         }
-        Some(Commands::Backup) => {
-            backup();
+        Some(Commands::Backup) => match backup(conn) {
+            Ok(json) => println!("{}", json),
+            Err(err) => eprintln!("{}", err),
+        },
+        Some(Commands::Restore { replace }) => {
+            let mut input = String::new();
+            stdin()
+                .read_to_string(&mut input)
+                .expect("Failed to read input");
+            match restore(conn, &input, replace) {
+                Ok(summary) => println!(
+                    "Restored {} sources, {} pages and {} history entries",
+                    summary.sources, summary.pages, summary.history
+                ),
+                Err(err) => eprintln!("Restore failed: {}", err),
+            }
+        }
+        Some(Commands::Server {
+            bind,
+            port,
+            base_path,
+        }) => {
+            let options = ServerOptions {
+                bind,
+                port,
+                base_path,
+            };
+            // Hand the connection back, the server uses the whole pool
+            drop(pooled);
+            if let Err(err) = server(&pool, strategy.as_ref(), &config, &options) {
+                eprintln!("Server error: {}", err);
+                logger.shutdown();
+                process::exit(1);
+            }
+        }
+        Some(Commands::ImportOpml { file }) => match import_opml(conn, &file) {
+            Ok(summary) => println!(
+                "Imported {} sources, skipped {} duplicates",
+                summary.imported, summary.skipped
+            ),
+            Err(err) => println!("Failed to import OPML: {}", err),
+        },
+        Some(Commands::ExportOpml) => match export_opml(conn) {
+            Ok(opml) => println!("{}", opml),
+            Err(err) => println!("Failed to export OPML: {}", err),
+        },
+        Some(Commands::History {
+            since,
+            source,
+            limit,
+        }) => {
+            let since = since.map(|date| date.and_time(NaiveTime::MIN));
+            let entries = get_history(conn, since, source, limit);
+            print_history(&entries);
+        }
+        Some(Commands::ExportFeed { kind, count }) => {
+            let kind = kind.unwrap_or(config.feed.kind);
+            let pages = feed_pages(
+                conn,
+                strategy.as_ref(),
+                kind,
+                count.unwrap_or(config.feed.count),
+            );
+            match atom_feed(conn, &config.feed, kind, &pages, None) {
+                Ok(feed) => println!("{}", feed),
+                Err(err) => println!("Failed to export feed: {}", err),
+            }
+        }
+        Some(Commands::HashPassword) => {
+            eprint!("Password or token: ");
+            let mut secret = String::new();
+            stdin().read_line(&mut secret).unwrap();
+            println!("{}", hash_secret(secret.trim_end_matches(['\r', '\n'])));
+        }
+        Some(Commands::Read { id }) => {
+            let page = get_page_by_id(conn, id)
+                .expect("Error loading page")
+                .expect("No page with that ID found.");
+            match get_or_archive_page(conn, &page) {
+                Ok(archive) => {
+                    println!("\x1B[1m{}\x1B[0m", archive.title);
+                    println!("{}\n", page.url);
+                    println!("{}", wrap_text(&archive.text, 80));
+                }
+                Err(err) => println!("Failed to archive page: {}", err),
+            }
+        }
+        Some(Commands::Search { query, limit }) => {
+            let results = search_pages(conn, &query.join(" "), limit);
+            print_search_results(&results);
         }
-        Some(Commands::Restore) => {
-            restore();
+        Some(Commands::Votes { id }) => {
+            let source = get_source_by_id(conn, id)
+                .expect("Error loading source")
+                .expect("No source with that ID found.");
+            let pages = get_voted_pages(conn, id);
+            print_votes(&source, &pages);
         }
-        Some(Commands::Server) => {
-            server(&pool);
+        Some(Commands::Explain { limit, source, tag }) => {
+            let filter = match (source, &tag) {
+                (Some(source), _) => PageFilter::Source(source),
+                (_, Some(tag)) => PageFilter::Tag(tag),
+                (None, None) => PageFilter::All,
+            };
+            let (explanations, candidates) =
+                explain_selection(conn, strategy.as_ref(), filter, limit);
+            print_explanation(&explanations, candidates);
         }
     }
+    logger.shutdown();
 }
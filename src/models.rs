@@ -1,4 +1,4 @@
-use crate::schema::{pages, sources};
+use crate::schema::{archives, history, pages, sources, tags};
 use chrono::NaiveDateTime;
 use diesel::{
     backend::Backend,
@@ -6,12 +6,15 @@ use diesel::{
     expression::AsExpression,
     prelude::*,
     serialize::{self, Output, ToSql},
-    sql_types::Integer,
+    sql_types::{Double, Integer, Nullable, Text, Timestamp},
 };
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
 #[repr(i32)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
 #[diesel(sql_type = Integer)]
+#[serde(rename_all = "lowercase")]
 pub enum SourceType {
     Rss = 1,
     Website = 2,
@@ -44,7 +47,17 @@ where
     }
 }
 
-#[derive(Queryable, Selectable, Identifiable, Debug, PartialEq, AsChangeset, Clone)]
+#[derive(
+    Queryable,
+    Selectable,
+    Identifiable,
+    Debug,
+    PartialEq,
+    AsChangeset,
+    Clone,
+    Serialize,
+    Deserialize,
+)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct Source {
     pub id: i32,
@@ -55,6 +68,24 @@ pub struct Source {
     pub etag: Option<String>,
     pub added: NaiveDateTime,
     pub title: String,
+    #[serde(default)]
+    pub folder: Option<String>,
+    #[serde(default)]
+    pub last_attempt: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub last_success: Option<NaiveDateTime>,
+    /// Consecutive failed fetches, 0 once a fetch succeeds.
+    #[serde(default)]
+    pub failures: i32,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Sync leaves the source alone until this time.
+    #[serde(default)]
+    pub next_fetch: Option<NaiveDateTime>,
+    /// How often the feed asks to be checked, from `<ttl>` or
+    /// `sy:updatePeriod`.
+    #[serde(default)]
+    pub refresh_minutes: Option<i32>,
 }
 
 #[derive(Insertable)]
@@ -63,9 +94,13 @@ pub struct NewSource {
     pub url: String,
     pub s_type: SourceType,
     pub title: String,
+    pub weight: Option<i32>,
+    pub folder: Option<String>,
 }
 
-#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[derive(
+    Queryable, Selectable, Identifiable, Associations, Debug, PartialEq, Serialize, Deserialize,
+)]
 #[diesel(belongs_to(Source))]
 pub struct Page {
     pub id: i32,
@@ -75,6 +110,24 @@ pub struct Page {
     pub read: Option<NaiveDateTime>,
     pub date: NaiveDateTime,
     pub added: NaiveDateTime,
+    /// 1 if upvoted, -1 if downvoted, 0 otherwise.
+    #[serde(default)]
+    pub vote: i32,
+    #[serde(default)]
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub content: Option<String>,
+    /// Comma separated author names.
+    #[serde(default)]
+    pub authors: Option<String>,
+    /// Comma separated category terms.
+    #[serde(default)]
+    pub categories: Option<String>,
+    /// Url of the first media attachment, e.g. a video.
+    #[serde(default)]
+    pub media: Option<String>,
+    #[serde(default)]
+    pub updated: Option<NaiveDateTime>,
 }
 
 #[derive(Insertable)]
@@ -85,4 +138,126 @@ pub struct NewPage {
     pub title: String,
     pub read: Option<NaiveDateTime>,
     pub date: Option<NaiveDateTime>,
+    pub summary: Option<String>,
+    pub content: Option<String>,
+    pub authors: Option<String>,
+    pub categories: Option<String>,
+    pub media: Option<String>,
+    pub updated: Option<NaiveDateTime>,
+}
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[diesel(sql_type = Integer)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryAction {
+    Opened = 1,
+    Upvote = 2,
+    Downvote = 3,
+    Skip = 4,
+    Unread = 5,
+}
+
+impl<DB> FromSql<Integer, DB> for HistoryAction
+where
+    DB: Backend,
+    i32: FromSql<Integer, DB>,
+{
+    fn from_sql(bytes: DB::RawValue<'_>) -> deserialize::Result<Self> {
+        match i32::from_sql(bytes)? {
+            1 => Ok(HistoryAction::Opened),
+            2 => Ok(HistoryAction::Upvote),
+            3 => Ok(HistoryAction::Downvote),
+            4 => Ok(HistoryAction::Skip),
+            5 => Ok(HistoryAction::Unread),
+            x => Err(format!("Invalid history action {}", x).into()),
+        }
+    }
+}
+
+impl<DB> ToSql<Integer, DB> for HistoryAction
+where
+    DB: Backend,
+    i32: ToSql<Integer, DB>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, DB>) -> serialize::Result {
+        match self {
+            HistoryAction::Opened => 1.to_sql(out),
+            HistoryAction::Upvote => 2.to_sql(out),
+            HistoryAction::Downvote => 3.to_sql(out),
+            HistoryAction::Skip => 4.to_sql(out),
+            HistoryAction::Unread => 5.to_sql(out),
+        }
+    }
+}
+
+impl fmt::Display for HistoryAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            HistoryAction::Opened => "opened",
+            HistoryAction::Upvote => "upvote",
+            HistoryAction::Downvote => "downvote",
+            HistoryAction::Skip => "skip",
+            HistoryAction::Unread => "unread",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[diesel(belongs_to(Page))]
+#[diesel(belongs_to(Source))]
+#[diesel(table_name = history)]
+pub struct HistoryEntry {
+    pub id: i32,
+    pub page_id: i32,
+    pub source_id: i32,
+    pub action: HistoryAction,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = history)]
+pub struct NewHistoryEntry {
+    pub page_id: i32,
+    pub source_id: i32,
+    pub action: HistoryAction,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug, PartialEq)]
+#[diesel(table_name = tags)]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+}
+
+/// A page matching a full text search, best match first.
+#[derive(QueryableByName, Debug)]
+pub struct SearchResult {
+    #[diesel(sql_type = Integer)]
+    pub page_id: i32,
+    #[diesel(sql_type = Text)]
+    pub title: String,
+    #[diesel(sql_type = Text)]
+    pub url: String,
+    #[diesel(sql_type = Nullable<Timestamp>)]
+    pub read: Option<NaiveDateTime>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub summary: Option<String>,
+    #[diesel(sql_type = Text)]
+    pub source_title: String,
+    #[diesel(sql_type = Double)]
+    pub rank: f64,
+}
+
+/// A cleaned up copy of a page's main text, kept in case the original goes away.
+#[derive(Queryable, Selectable, Identifiable, Associations, Insertable, Debug, PartialEq)]
+#[diesel(belongs_to(Page))]
+#[diesel(primary_key(page_id))]
+pub struct Archive {
+    pub page_id: i32,
+    pub title: String,
+    pub html: String,
+    pub text: String,
+    pub fetched: NaiveDateTime,
 }
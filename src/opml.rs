@@ -0,0 +1,330 @@
+use crate::crud::{get_sources, insert_source};
+use crate::models::{NewSource, Source, SourceType};
+use diesel::SqliteConnection;
+use log::info;
+use quick_xml::Reader;
+use quick_xml::Writer;
+use quick_xml::encoding::Decoder;
+use quick_xml::events::{BytesDecl, BytesStart, BytesText, Event};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+#[derive(Debug)]
+pub struct OpmlError {
+    message: String,
+}
+
+impl From<io::Error> for OpmlError {
+    fn from(error: io::Error) -> Self {
+        OpmlError {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<quick_xml::Error> for OpmlError {
+    fn from(error: quick_xml::Error) -> Self {
+        OpmlError {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for OpmlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OPML error: {}", self.message)
+    }
+}
+
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+/// Read the attributes of an outline element into (name, value) pairs.
+/// Names are lowercased as OPML in the wild is inconsistent about `xmlUrl`.
+fn outline_attributes(
+    element: &BytesStart,
+    decoder: Decoder,
+) -> Result<Vec<(String, String)>, OpmlError> {
+    element
+        .attributes()
+        .map(|attr| {
+            let attr = attr.map_err(quick_xml::Error::from)?;
+            let key = String::from_utf8_lossy(attr.key.as_ref()).to_lowercase();
+            let value = attr.decode_and_unescape_value(decoder)?.to_string();
+            Ok((key, value))
+        })
+        .collect()
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+        .filter(|value| !value.is_empty())
+}
+
+/// Turn an outline into a source, or None if the outline is a folder.
+fn outline_to_source(attributes: &[(String, String)], folders: &[String]) -> Option<NewSource> {
+    let (url, s_type) = if let Some(url) = attribute(attributes, "xmlurl") {
+        (url, SourceType::Rss)
+    } else if attribute(attributes, "type") == Some("link") {
+        (attribute(attributes, "url")?, SourceType::Website)
+    } else {
+        return None;
+    };
+    let title = attribute(attributes, "title")
+        .or(attribute(attributes, "text"))
+        .unwrap_or(url);
+    Some(NewSource {
+        url: url.to_string(),
+        s_type,
+        title: title.to_string(),
+        weight: attribute(attributes, "weight").and_then(|weight| weight.parse().ok()),
+        folder: (!folders.is_empty()).then(|| folders.join("/")),
+    })
+}
+
+pub fn parse_opml(body: &str) -> Result<Vec<NewSource>, OpmlError> {
+    let mut reader = Reader::from_str(body);
+    reader.config_mut().trim_text(true);
+    let mut new_sources = Vec::new();
+    // Folder names of the enclosing outlines, None for non-folder outlines
+    let mut stack: Vec<Option<String>> = Vec::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) if element.name().as_ref() == b"outline" => {
+                let attributes = outline_attributes(&element, reader.decoder())?;
+                let folders: Vec<String> = stack.iter().flatten().cloned().collect();
+                match outline_to_source(&attributes, &folders) {
+                    Some(new_source) => {
+                        new_sources.push(new_source);
+                        stack.push(None);
+                    }
+                    None => {
+                        let name = attribute(&attributes, "text")
+                            .or(attribute(&attributes, "title"))
+                            .unwrap_or("Untitled");
+                        stack.push(Some(name.to_string()));
+                    }
+                }
+            }
+            Event::Empty(element) if element.name().as_ref() == b"outline" => {
+                let attributes = outline_attributes(&element, reader.decoder())?;
+                let folders: Vec<String> = stack.iter().flatten().cloned().collect();
+                if let Some(new_source) = outline_to_source(&attributes, &folders) {
+                    new_sources.push(new_source);
+                }
+            }
+            Event::End(element) if element.name().as_ref() == b"outline" => {
+                stack.pop();
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+    Ok(new_sources)
+}
+
+/// Import sources from an OPML file. Pages are not fetched, they will be
+/// downloaded on the next sync.
+pub fn import_opml(conn: &mut SqliteConnection, path: &Path) -> Result<ImportSummary, OpmlError> {
+    let body = fs::read_to_string(path)?;
+    let new_sources = parse_opml(&body)?;
+    let mut summary = ImportSummary {
+        imported: 0,
+        skipped: 0,
+    };
+    for new_source in new_sources {
+        match insert_source(conn, &new_source) {
+            Some(source) => {
+                info!("Imported source {} from OPML", source.id);
+                summary.imported += 1;
+            }
+            None => summary.skipped += 1,
+        }
+    }
+    Ok(summary)
+}
+
+#[derive(Default)]
+struct Folder<'a> {
+    sources: Vec<&'a Source>,
+    children: BTreeMap<&'a str, Folder<'a>>,
+}
+
+fn write_source<W: io::Write>(writer: &mut Writer<W>, source: &Source) -> io::Result<()> {
+    let weight = source.weight.to_string();
+    let mut element = writer.create_element("outline").with_attributes([
+        ("text", source.title.as_str()),
+        ("title", source.title.as_str()),
+    ]);
+    element = match source.s_type {
+        SourceType::Rss => element.with_attributes([("type", "rss"), ("xmlUrl", &source.url)]),
+        SourceType::Website => element.with_attributes([("type", "link"), ("url", &source.url)]),
+    };
+    element
+        .with_attribute(("weight", weight.as_str()))
+        .write_empty()?;
+    Ok(())
+}
+
+fn write_folder<W: io::Write>(writer: &mut Writer<W>, folder: &Folder) -> io::Result<()> {
+    for (name, child) in &folder.children {
+        writer
+            .create_element("outline")
+            .with_attributes([("text", *name), ("title", *name)])
+            .write_inner_content(|writer| write_folder(writer, child))?;
+    }
+    for source in &folder.sources {
+        write_source(writer, source)?;
+    }
+    Ok(())
+}
+
+pub fn export_opml(conn: &mut SqliteConnection) -> Result<String, OpmlError> {
+    let sources = get_sources(conn).expect("Error loading sources");
+    let mut root = Folder::default();
+    for source in &sources {
+        let folder = match &source.folder {
+            Some(path) => path.split('/').fold(&mut root, |folder, name| {
+                folder.children.entry(name).or_default()
+            }),
+            None => &mut root,
+        };
+        folder.sources.push(source);
+    }
+
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer
+        .create_element("opml")
+        .with_attribute(("version", "2.0"))
+        .write_inner_content(|writer| {
+            writer
+                .create_element("head")
+                .write_inner_content(|writer| {
+                    writer
+                        .create_element("title")
+                        .write_text_content(BytesText::new("My Web Ring sources"))?;
+                    Ok(())
+                })?;
+            writer
+                .create_element("body")
+                .write_inner_content(|writer| write_folder(writer, &root))?;
+            Ok(())
+        })?;
+    Ok(String::from_utf8_lossy(&writer.into_inner()).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (url, title, folder) of every parsed source.
+    fn parse(body: &str) -> Vec<(String, String, Option<String>)> {
+        parse_opml(body)
+            .unwrap()
+            .into_iter()
+            .map(|source| (source.url, source.title, source.folder))
+            .collect()
+    }
+
+    fn source(url: &str, title: &str, folder: Option<&str>) -> (String, String, Option<String>) {
+        (
+            url.to_string(),
+            title.to_string(),
+            folder.map(str::to_string),
+        )
+    }
+
+    #[test]
+    fn nested_folders_are_joined_with_slashes() {
+        let body = r#"<opml version="2.0"><body>
+            <outline text="Tech">
+                <outline text="Rust">
+                    <outline text="Blog" xmlUrl="https://blog.example/feed.xml"/>
+                </outline>
+                <outline text="News" xmlUrl="https://news.example/rss"></outline>
+            </outline>
+            <outline text="Top" xmlUrl="https://top.example/atom.xml"/>
+        </body></opml>"#;
+        assert_eq!(
+            parse(body),
+            vec![
+                source("https://blog.example/feed.xml", "Blog", Some("Tech/Rust")),
+                source("https://news.example/rss", "News", Some("Tech")),
+                source("https://top.example/atom.xml", "Top", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn outlines_inside_a_feed_outline_are_not_in_a_folder() {
+        let body = r#"<opml><body>
+            <outline text="Feed" xmlUrl="https://a.example/feed">
+                <outline text="Child" xmlUrl="https://b.example/feed"/>
+            </outline>
+        </body></opml>"#;
+        assert_eq!(
+            parse(body),
+            vec![
+                source("https://a.example/feed", "Feed", None),
+                source("https://b.example/feed", "Child", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn attribute_names_are_case_insensitive() {
+        let body = r#"<opml><body>
+            <outline title="Lower" xmlurl="https://a.example/feed" weight="5"/>
+            <outline TEXT="Upper" XMLURL="https://b.example/feed"/>
+        </body></opml>"#;
+        let sources = parse_opml(body).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].url, "https://a.example/feed");
+        assert_eq!(sources[0].title, "Lower");
+        assert_eq!(sources[0].weight, Some(5));
+        assert_eq!(sources[1].url, "https://b.example/feed");
+        assert_eq!(sources[1].title, "Upper");
+        assert_eq!(sources[1].weight, None);
+    }
+
+    #[test]
+    fn link_outlines_become_websites() {
+        let body = r#"<opml><body>
+            <outline text="Home" type="link" url="https://home.example/"/>
+            <outline text="No url" type="link"/>
+            <outline text="Feed" type="rss" xmlUrl="https://feed.example/rss"/>
+        </body></opml>"#;
+        let sources = parse_opml(body).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].url, "https://home.example/");
+        assert_eq!(sources[0].s_type, SourceType::Website);
+        assert_eq!(sources[1].s_type, SourceType::Rss);
+    }
+
+    #[test]
+    fn titles_fall_back_to_the_url() {
+        let body =
+            r#"<opml><body><outline xmlUrl="https://a.example/feed?a=1&amp;b=2"/></body></opml>"#;
+        assert_eq!(
+            parse(body),
+            vec![source(
+                "https://a.example/feed?a=1&b=2",
+                "https://a.example/feed?a=1&b=2",
+                None
+            )]
+        );
+    }
+
+    #[test]
+    fn malformed_xml_is_an_error() {
+        assert!(parse_opml("<opml><body><outline xmlUrl=\"x\"></body></opml>").is_err());
+    }
+}
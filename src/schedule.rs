@@ -0,0 +1,222 @@
+use crate::config::SyncConfig;
+use chrono::{DateTime, Duration, NaiveDateTime};
+use quick_xml::NsReader;
+use quick_xml::events::Event;
+use quick_xml::name::{Namespace, ResolveResult};
+
+const SYNDICATION_NS: &[u8] = b"http://purl.org/rss/1.0/modules/syndication/";
+
+/// `max-age` from a Cache-Control header, None if the response isn't meant
+/// to be cached.
+pub fn cache_max_age(cache_control: &str) -> Option<Duration> {
+    let mut max_age = None;
+    for directive in cache_control.split(',').map(str::trim) {
+        if directive.eq_ignore_ascii_case("no-cache") || directive.eq_ignore_ascii_case("no-store")
+        {
+            return None;
+        }
+        match directive.split_once('=') {
+            Some((name, value)) if name.trim().eq_ignore_ascii_case("max-age") => {
+                max_age = value
+                    .trim()
+                    .trim_matches('"')
+                    .parse()
+                    .ok()
+                    .and_then(Duration::try_seconds);
+            }
+            _ => {}
+        }
+    }
+    max_age
+}
+
+/// A Retry-After header, either a number of seconds or an HTTP date.
+pub fn retry_after(value: &str, now: NaiveDateTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<i64>() {
+        return Duration::try_seconds(seconds.max(0));
+    }
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| (date.naive_utc() - now).max(Duration::zero()))
+}
+
+fn update_period(period: &str) -> Option<Duration> {
+    match period.trim() {
+        "hourly" => Some(Duration::hours(1)),
+        "daily" => Some(Duration::days(1)),
+        "weekly" => Some(Duration::weeks(1)),
+        "monthly" => Some(Duration::days(30)),
+        "yearly" => Some(Duration::days(365)),
+        _ => None,
+    }
+}
+
+/// Refresh interval from the RSS syndication module: `sy:updatePeriod`
+/// divided by `sy:updateFrequency`.
+pub fn syndication_interval(body: &str) -> Option<Duration> {
+    let mut reader = NsReader::from_str(body);
+    let mut period = None;
+    let mut frequency: i32 = 1;
+    loop {
+        match reader.read_resolved_event() {
+            Ok((ResolveResult::Bound(Namespace(SYNDICATION_NS)), Event::Start(element))) => {
+                let Ok(text) = reader.read_text(element.name()) else {
+                    break;
+                };
+                match element.local_name().as_ref() {
+                    b"updatePeriod" => period = update_period(&text),
+                    b"updateFrequency" => frequency = text.trim().parse().unwrap_or(1),
+                    _ => {}
+                }
+            }
+            // The channel settings come before the items
+            Ok((_, Event::Start(element)))
+                if matches!(element.local_name().as_ref(), b"item" | b"entry") =>
+            {
+                break;
+            }
+            Ok((_, Event::Eof)) | Err(_) => break,
+            _ => {}
+        }
+    }
+    period.map(|period| period / frequency.max(1))
+}
+
+/// When to fetch a source again. The longest interval the feed or server
+/// asked for wins, kept between the configured minimum and maximum.
+pub fn next_fetch(
+    now: NaiveDateTime,
+    hints: &[Option<Duration>],
+    config: &SyncConfig,
+) -> NaiveDateTime {
+    let min = Duration::minutes(config.min_interval_minutes);
+    let max = Duration::hours(config.max_interval_hours).max(min);
+    now + hints.iter().flatten().fold(min, |a, &b| a.max(b)).min(max)
+}
+
+/// When to try a rate limited source again, as asked by Retry-After. The
+/// server may ask for longer than the usual interval, only the backoff limit
+/// caps it.
+pub fn retry_at(now: NaiveDateTime, delay: Duration, config: &SyncConfig) -> NaiveDateTime {
+    now + delay.min(Duration::hours(config.max_backoff_hours))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    fn feed(channel: &str) -> String {
+        format!(
+            "<rss version=\"2.0\" xmlns:sy=\"http://purl.org/rss/1.0/modules/syndication/\">\
+             <channel><title>Feed</title>{}<item><title>Item</title></item></channel></rss>",
+            channel
+        )
+    }
+
+    #[test]
+    fn cache_max_age_reads_max_age() {
+        assert_eq!(cache_max_age("max-age=3600"), Some(Duration::hours(1)));
+        assert_eq!(
+            cache_max_age("public, Max-Age=\"7200\""),
+            Some(Duration::hours(2))
+        );
+        assert_eq!(cache_max_age("public"), None);
+        assert_eq!(cache_max_age("max-age=soon"), None);
+    }
+
+    #[test]
+    fn cache_max_age_ignores_no_cache_and_no_store() {
+        assert_eq!(cache_max_age("max-age=3600, no-cache"), None);
+        assert_eq!(cache_max_age("no-store, max-age=3600"), None);
+        assert_eq!(cache_max_age("No-Cache"), None);
+    }
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(retry_after("120", now()), Some(Duration::minutes(2)));
+        assert_eq!(retry_after(" 0 ", now()), Some(Duration::zero()));
+        assert_eq!(retry_after("-5", now()), Some(Duration::zero()));
+        assert_eq!(retry_after("later", now()), None);
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        assert_eq!(
+            retry_after("Thu, 01 Jan 2026 13:30:00 GMT", now()),
+            Some(Duration::minutes(90))
+        );
+        // A date in the past means now
+        assert_eq!(
+            retry_after("Thu, 01 Jan 2026 11:00:00 GMT", now()),
+            Some(Duration::zero())
+        );
+    }
+
+    #[test]
+    fn syndication_interval_divides_period_by_frequency() {
+        let body = feed(
+            "<sy:updatePeriod>daily</sy:updatePeriod><sy:updateFrequency>2</sy:updateFrequency>",
+        );
+        assert_eq!(syndication_interval(&body), Some(Duration::hours(12)));
+        let body = feed("<sy:updatePeriod> hourly </sy:updatePeriod>");
+        assert_eq!(syndication_interval(&body), Some(Duration::hours(1)));
+        let body = feed(
+            "<sy:updatePeriod>weekly</sy:updatePeriod><sy:updateFrequency>0</sy:updateFrequency>",
+        );
+        assert_eq!(syndication_interval(&body), Some(Duration::weeks(1)));
+    }
+
+    #[test]
+    fn syndication_interval_needs_a_known_period() {
+        assert_eq!(syndication_interval(&feed("")), None);
+        let body = feed("<sy:updatePeriod>fortnightly</sy:updatePeriod>");
+        assert_eq!(syndication_interval(&body), None);
+        // Only elements in the syndication namespace count
+        let body = "<rss><channel><updatePeriod>daily</updatePeriod></channel></rss>";
+        assert_eq!(syndication_interval(body), None);
+    }
+
+    #[test]
+    fn next_fetch_takes_the_longest_hint_within_limits() {
+        let config = SyncConfig::default();
+        assert_eq!(next_fetch(now(), &[], &config), now() + Duration::hours(1));
+        assert_eq!(
+            next_fetch(
+                now(),
+                &[Some(Duration::hours(3)), None, Some(Duration::hours(2))],
+                &config
+            ),
+            now() + Duration::hours(3)
+        );
+        assert_eq!(
+            next_fetch(now(), &[Some(Duration::minutes(5))], &config),
+            now() + Duration::hours(1)
+        );
+        assert_eq!(
+            next_fetch(now(), &[Some(Duration::weeks(1))], &config),
+            now() + Duration::hours(24)
+        );
+    }
+
+    #[test]
+    fn retry_at_honours_delays_longer_than_the_max_interval() {
+        let config = SyncConfig::default();
+        assert_eq!(
+            retry_at(now(), Duration::hours(48), &config),
+            now() + Duration::hours(48)
+        );
+        assert_eq!(
+            retry_at(now(), Duration::weeks(4), &config),
+            now() + Duration::hours(config.max_backoff_hours)
+        );
+    }
+}
@@ -1,5 +1,25 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    archives (page_id) {
+        page_id -> Integer,
+        title -> Text,
+        html -> Text,
+        text -> Text,
+        fetched -> Timestamp,
+    }
+}
+
+diesel::table! {
+    history (id) {
+        id -> Integer,
+        page_id -> Integer,
+        source_id -> Integer,
+        action -> Integer,
+        created -> Timestamp,
+    }
+}
+
 diesel::table! {
     pages (id) {
         id -> Integer,
@@ -9,6 +29,20 @@ diesel::table! {
         read -> Nullable<Timestamp>,
         date -> Timestamp,
         added -> Timestamp,
+        vote -> Integer,
+        summary -> Nullable<Text>,
+        content -> Nullable<Text>,
+        authors -> Nullable<Text>,
+        categories -> Nullable<Text>,
+        media -> Nullable<Text>,
+        updated -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    source_tags (source_id, tag_id) {
+        source_id -> Integer,
+        tag_id -> Integer,
     }
 }
 
@@ -22,12 +56,35 @@ diesel::table! {
         etag -> Nullable<Text>,
         added -> Timestamp,
         title -> Text,
+        folder -> Nullable<Text>,
+        last_attempt -> Nullable<Timestamp>,
+        last_success -> Nullable<Timestamp>,
+        failures -> Integer,
+        last_error -> Nullable<Text>,
+        next_fetch -> Nullable<Timestamp>,
+        refresh_minutes -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    tags (id) {
+        id -> Integer,
+        name -> Text,
     }
 }
 
+diesel::joinable!(archives -> pages (page_id));
+diesel::joinable!(history -> pages (page_id));
+diesel::joinable!(history -> sources (source_id));
 diesel::joinable!(pages -> sources (source_id));
+diesel::joinable!(source_tags -> sources (source_id));
+diesel::joinable!(source_tags -> tags (tag_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    archives,
+    history,
     pages,
+    source_tags,
     sources,
+    tags,
 );
@@ -0,0 +1,478 @@
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use rand::{Rng, RngCore};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// An unread page that can be selected, along with what the strategies
+/// need to know about it and its source.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub page_id: i32,
+    pub source_id: i32,
+    pub weight: i32,
+    pub date: NaiveDateTime,
+    /// When a page from the same source was last read.
+    pub source_last_read: Option<NaiveDateTime>,
+}
+
+/// How a candidate's odds were computed, kept around for debugging.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score {
+    /// Weight of the page's source.
+    pub weight: i32,
+    /// Multiplier applied for the page's age.
+    pub recency: f64,
+    /// Relative odds of being selected.
+    pub total: f64,
+}
+
+/// 1.0 for a page dated now, halving every `half_life_days` of age.
+pub fn recency(date: NaiveDateTime, now: NaiveDateTime, half_life_days: f64) -> f64 {
+    let age_days = (now - date).num_seconds().max(0) as f64 / 86400.0;
+    0.5f64.powf(age_days / half_life_days)
+}
+
+pub trait SelectionStrategy: Send + Sync {
+    /// Score each candidate, in the same order as `candidates`.
+    /// Deterministic strategies give a single candidate a non zero total.
+    fn scores(&self, candidates: &[Candidate], now: NaiveDateTime) -> Vec<Score>;
+
+    /// Pick the index of a candidate based on its score.
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        now: NaiveDateTime,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        let mut odds: Vec<f64> = self
+            .scores(candidates, now)
+            .iter()
+            .map(|score| score.total)
+            .collect();
+        let mut total: f64 = odds.iter().sum();
+        if !total.is_finite() || total <= 0.0 {
+            // Every source has been downvoted to 0 or the scores are
+            // unusable, fall back to uniform
+            odds = vec![1.0; candidates.len()];
+            total = odds.len() as f64;
+        }
+        let mut pick = rng.random_range(0.0..total);
+        for (index, odd) in odds.iter().enumerate() {
+            if pick < *odd {
+                return Some(index);
+            }
+            pick -= odd;
+        }
+        // Floating point rounding can leave a sliver past the last candidate
+        odds.iter().rposition(|odd| *odd > 0.0)
+    }
+}
+
+/// Source weight, up to doubled for brand new pages. The bonus halves every
+/// `half_life_days` of page age.
+pub struct Weighted {
+    pub half_life_days: f64,
+}
+
+impl SelectionStrategy for Weighted {
+    fn scores(&self, candidates: &[Candidate], now: NaiveDateTime) -> Vec<Score> {
+        candidates
+            .iter()
+            .map(|candidate| {
+                let recency = 1.0 + recency(candidate.date, now, self.half_life_days);
+                Score {
+                    weight: candidate.weight,
+                    recency,
+                    total: candidate.weight as f64 * recency,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Source weight halved every `half_life_days` of page age.
+pub struct TimeDecay {
+    pub half_life_days: f64,
+}
+
+impl SelectionStrategy for TimeDecay {
+    fn scores(&self, candidates: &[Candidate], now: NaiveDateTime) -> Vec<Score> {
+        candidates
+            .iter()
+            .map(|candidate| {
+                let recency = recency(candidate.date, now, self.half_life_days);
+                Score {
+                    weight: candidate.weight,
+                    recency,
+                    total: candidate.weight as f64 * recency,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The newest page from the source that was read from least recently.
+pub struct RoundRobin;
+
+impl SelectionStrategy for RoundRobin {
+    fn scores(&self, candidates: &[Candidate], _now: NaiveDateTime) -> Vec<Score> {
+        let mut newest: HashMap<i32, usize> = HashMap::new();
+        for (index, candidate) in candidates.iter().enumerate() {
+            let entry = newest.entry(candidate.source_id).or_insert(index);
+            if candidate.date > candidates[*entry].date {
+                *entry = index;
+            }
+        }
+        // Never read sources sort first as None < Some
+        let chosen = newest
+            .values()
+            .min_by_key(|index| {
+                let candidate = &candidates[**index];
+                (candidate.source_last_read, candidate.source_id)
+            })
+            .copied();
+        only(candidates, chosen)
+    }
+}
+
+/// Pick a source by weight first, then a page within it, so a feed with
+/// hundreds of unread pages doesn't drown out one with a handful.
+/// A source's odds are its weight times its unread count raised to
+/// `unread_exponent`: at 0.0 they depend only on its weight, at 1.0 this is
+/// the same as weighting every page by its source. Within a source newer pages are
+/// favoured the same way as with `Weighted`.
+pub struct SourceFirst {
+    pub unread_exponent: f64,
+    pub half_life_days: f64,
+}
+
+impl SelectionStrategy for SourceFirst {
+    fn scores(&self, candidates: &[Candidate], now: NaiveDateTime) -> Vec<Score> {
+        let recencies: Vec<f64> = candidates
+            .iter()
+            .map(|candidate| 1.0 + recency(candidate.date, now, self.half_life_days))
+            .collect();
+        // Unread count and summed recency per source
+        let mut sources: HashMap<i32, (usize, f64)> = HashMap::new();
+        for (candidate, recency) in candidates.iter().zip(&recencies) {
+            let entry = sources.entry(candidate.source_id).or_default();
+            entry.0 += 1;
+            entry.1 += recency;
+        }
+        candidates
+            .iter()
+            .zip(recencies)
+            .map(|(candidate, recency)| {
+                let (count, recency_sum) = sources[&candidate.source_id];
+                let source_odds =
+                    candidate.weight as f64 * (count as f64).powf(self.unread_exponent);
+                Score {
+                    weight: candidate.weight,
+                    recency,
+                    total: source_odds * recency / recency_sum,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Every unread page has the same odds.
+pub struct Uniform;
+
+impl SelectionStrategy for Uniform {
+    fn scores(&self, candidates: &[Candidate], _now: NaiveDateTime) -> Vec<Score> {
+        candidates
+            .iter()
+            .map(|candidate| Score {
+                weight: candidate.weight,
+                recency: 1.0,
+                total: 1.0,
+            })
+            .collect()
+    }
+}
+
+/// The unread page with the oldest date.
+pub struct OldestFirst;
+
+impl SelectionStrategy for OldestFirst {
+    fn scores(&self, candidates: &[Candidate], _now: NaiveDateTime) -> Vec<Score> {
+        let chosen = candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, candidate)| candidate.date)
+            .map(|(index, _)| index);
+        only(candidates, chosen)
+    }
+}
+
+fn only(candidates: &[Candidate], chosen: Option<usize>) -> Vec<Score> {
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| Score {
+            weight: candidate.weight,
+            recency: 1.0,
+            total: if Some(index) == chosen { 1.0 } else { 0.0 },
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Source weight with a bonus for newer pages (default)
+    #[default]
+    Weighted,
+    /// Source weight decaying exponentially with page age
+    TimeDecay,
+    /// Newest page from the least recently read source
+    RoundRobin,
+    /// Source by weight first, then a page within it
+    SourceFirst,
+    /// Every unread page is equally likely
+    Uniform,
+    /// Oldest unread page first
+    OldestFirst,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SelectionConfig {
+    pub strategy: Strategy,
+    /// Page age in days after which its recency bonus has halved.
+    pub half_life_days: f64,
+    /// Power of the unread count in a source's odds with `source-first`,
+    /// between 0 (ignored) and 1 (every page counts).
+    pub unread_exponent: f64,
+}
+
+impl Default for SelectionConfig {
+    fn default() -> Self {
+        SelectionConfig {
+            strategy: Strategy::default(),
+            half_life_days: 7.0,
+            unread_exponent: 0.0,
+        }
+    }
+}
+
+impl SelectionConfig {
+    pub fn build(&self) -> Box<dyn SelectionStrategy> {
+        match self.strategy {
+            Strategy::Weighted => Box::new(Weighted {
+                half_life_days: self.half_life_days,
+            }),
+            Strategy::TimeDecay => Box::new(TimeDecay {
+                half_life_days: self.half_life_days,
+            }),
+            Strategy::RoundRobin => Box::new(RoundRobin),
+            Strategy::SourceFirst => Box::new(SourceFirst {
+                unread_exponent: self.unread_exponent,
+                half_life_days: self.half_life_days,
+            }),
+            Strategy::Uniform => Box::new(Uniform),
+            Strategy::OldestFirst => Box::new(OldestFirst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+    }
+
+    fn candidate(page_id: i32, source_id: i32, weight: i32, age_days: i64) -> Candidate {
+        Candidate {
+            page_id,
+            source_id,
+            weight,
+            date: now() - Duration::days(age_days),
+            source_last_read: None,
+        }
+    }
+
+    /// How often each candidate is picked out of `draws`.
+    fn pick_counts(
+        strategy: &dyn SelectionStrategy,
+        candidates: &[Candidate],
+        draws: usize,
+    ) -> Vec<usize> {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut counts = vec![0; candidates.len()];
+        for _ in 0..draws {
+            let index = strategy.select(candidates, now(), &mut rng).unwrap();
+            counts[index] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn nothing_to_pick() {
+        let mut rng = StdRng::seed_from_u64(42);
+        assert_eq!(Uniform.select(&[], now(), &mut rng), None);
+    }
+
+    #[test]
+    fn weighted_skips_zero_weight_and_favours_new_pages() {
+        let strategy = Weighted {
+            half_life_days: 7.0,
+        };
+        let candidates = [candidate(1, 1, 0, 0), candidate(2, 2, 1, 0)];
+        assert_eq!(pick_counts(&strategy, &candidates, 1000), [0, 1000]);
+
+        // A new page scores 2, one 7 days old 1.5
+        let candidates = [candidate(1, 1, 1, 0), candidate(2, 2, 1, 7)];
+        let counts = pick_counts(&strategy, &candidates, 10000);
+        let share = counts[0] as f64 / 10000.0;
+        assert!((share - 2.0 / 3.5).abs() < 0.02, "share {}", share);
+    }
+
+    #[test]
+    fn time_decay_halves_every_half_life() {
+        let strategy = TimeDecay {
+            half_life_days: 7.0,
+        };
+        let candidates = [candidate(1, 1, 4, 0), candidate(2, 2, 4, 14)];
+        let scores = strategy.scores(&candidates, now());
+        assert_eq!(scores[0].total, 4.0);
+        assert!((scores[1].total - 1.0).abs() < 1e-9);
+        let counts = pick_counts(&strategy, &candidates, 10000);
+        let share = counts[0] as f64 / 10000.0;
+        assert!((share - 0.8).abs() < 0.02, "share {}", share);
+    }
+
+    #[test]
+    fn round_robin_picks_newest_page_of_least_recently_read_source() {
+        let mut candidates = vec![
+            candidate(1, 1, 1, 3),
+            candidate(2, 2, 1, 5),
+            candidate(3, 2, 1, 1),
+        ];
+        candidates[0].source_last_read = Some(now() - Duration::days(1));
+        candidates[1].source_last_read = Some(now() - Duration::days(2));
+        candidates[2].source_last_read = Some(now() - Duration::days(2));
+        assert_eq!(pick_counts(&RoundRobin, &candidates, 100), [0, 0, 100]);
+    }
+
+    #[test]
+    fn oldest_first_picks_the_oldest_page() {
+        let candidates = [
+            candidate(1, 1, 1, 3),
+            candidate(2, 2, 1, 9),
+            candidate(3, 3, 1, 1),
+        ];
+        assert_eq!(pick_counts(&OldestFirst, &candidates, 100), [0, 100, 0]);
+    }
+
+    #[test]
+    fn uniform_ignores_weights() {
+        let candidates = [candidate(1, 1, 1, 0), candidate(2, 2, 50, 30)];
+        let counts = pick_counts(&Uniform, &candidates, 10000);
+        let share = counts[0] as f64 / 10000.0;
+        assert!((share - 0.5).abs() < 0.02, "share {}", share);
+    }
+
+    #[test]
+    fn all_zero_weights_fall_back_to_uniform() {
+        let strategy = Weighted {
+            half_life_days: 7.0,
+        };
+        let candidates = [candidate(1, 1, 0, 0), candidate(2, 2, 0, 0)];
+        let counts = pick_counts(&strategy, &candidates, 10000);
+        let share = counts[0] as f64 / 10000.0;
+        assert!((share - 0.5).abs() < 0.02, "share {}", share);
+    }
+
+    #[test]
+    fn unusable_scores_fall_back_to_uniform() {
+        // A page dated now scores NaN with a half life of 0
+        let strategy = Weighted {
+            half_life_days: 0.0,
+        };
+        let candidates = [candidate(1, 1, 1, 0), candidate(2, 2, 1, 3)];
+        let counts = pick_counts(&strategy, &candidates, 10000);
+        let share = counts[0] as f64 / 10000.0;
+        assert!((share - 0.5).abs() < 0.02, "share {}", share);
+    }
+
+    /// Share of `draws` picks that land on each source.
+    fn source_shares(
+        strategy: &SourceFirst,
+        candidates: &[Candidate],
+        draws: usize,
+    ) -> HashMap<i32, f64> {
+        let mut shares = HashMap::new();
+        for (candidate, count) in candidates
+            .iter()
+            .zip(pick_counts(strategy, candidates, draws))
+        {
+            *shares.entry(candidate.source_id).or_default() += count as f64 / draws as f64;
+        }
+        shares
+    }
+
+    /// Source 1 with weight 1 and a single page, source 2 with weight 3 and
+    /// four pages.
+    fn weighted_sources() -> Vec<Candidate> {
+        let mut candidates = vec![candidate(1, 1, 1, 0)];
+        candidates.extend((2..6).map(|page_id| candidate(page_id, 2, 3, 0)));
+        candidates
+    }
+
+    #[test]
+    fn source_first_follows_source_weights() {
+        let strategy = SourceFirst {
+            unread_exponent: 0.0,
+            half_life_days: 7.0,
+        };
+        let shares = source_shares(&strategy, &weighted_sources(), 20000);
+        assert!((shares[&1] - 0.25).abs() < 0.02, "shares {:?}", shares);
+        assert!((shares[&2] - 0.75).abs() < 0.02, "shares {:?}", shares);
+    }
+
+    #[test]
+    fn source_first_unread_exponent_one_weights_every_page() {
+        let strategy = SourceFirst {
+            unread_exponent: 1.0,
+            half_life_days: 7.0,
+        };
+        // Odds of 1 * 1 against 3 * 4
+        let shares = source_shares(&strategy, &weighted_sources(), 20000);
+        assert!(
+            (shares[&1] - 1.0 / 13.0).abs() < 0.02,
+            "shares {:?}",
+            shares
+        );
+        assert!(
+            (shares[&2] - 12.0 / 13.0).abs() < 0.02,
+            "shares {:?}",
+            shares
+        );
+    }
+
+    #[test]
+    fn source_first_many_pages_do_not_dominate() {
+        let strategy = SourceFirst {
+            unread_exponent: 0.0,
+            half_life_days: 7.0,
+        };
+        let mut candidates = vec![candidate(1, 1, 1, 0)];
+        candidates.extend((2..102).map(|page_id| candidate(page_id, 2, 1, 0)));
+        let shares = source_shares(&strategy, &candidates, 20000);
+        assert!((shares[&1] - 0.5).abs() < 0.02, "shares {:?}", shares);
+    }
+}
@@ -0,0 +1,152 @@
+use std::any::Any;
+use std::collections::{HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex, mpsc};
+use std::thread;
+use url::Url;
+
+/// Host part of a URL, used to keep requests to the same site apart.
+pub fn host_of(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_lowercase))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// The message a thread panicked with, if it was a string.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+struct Queue<J> {
+    /// Jobs with their host.
+    pending: VecDeque<(J, String)>,
+    /// Hosts a worker is currently talking to.
+    busy: HashSet<String>,
+}
+
+/// Take the first job whose host is free, waiting for one to free up. None
+/// once every job has been taken.
+fn next_job<J>(queue: &Mutex<Queue<J>>, freed: &Condvar) -> Option<(J, String)> {
+    let mut queue = queue.lock().unwrap();
+    loop {
+        if queue.pending.is_empty() {
+            return None;
+        }
+        if let Some(index) = queue
+            .pending
+            .iter()
+            .position(|(_, key)| !queue.busy.contains(key))
+        {
+            let (job, key) = queue.pending.remove(index).unwrap();
+            queue.busy.insert(key.clone());
+            return Some((job, key));
+        }
+        queue = freed.wait(queue).unwrap();
+    }
+}
+
+/// Run `work` on every job with at most `workers` threads and at most one
+/// job per host at a time. Results are handed to `done` on the calling
+/// thread as they come in, so only it needs a database connection. If `work`
+/// panics, `done` gets the panic message instead.
+pub fn run_per_host<J, R>(
+    jobs: Vec<J>,
+    workers: usize,
+    host: impl Fn(&J) -> String,
+    work: impl Fn(&J) -> R + Sync,
+    mut done: impl FnMut(J, Result<R, String>),
+) where
+    J: Send,
+    R: Send,
+{
+    let workers = workers.clamp(1, jobs.len().max(1));
+    let queue = Mutex::new(Queue {
+        pending: jobs
+            .into_iter()
+            .map(|job| {
+                let key = host(&job);
+                (job, key)
+            })
+            .collect(),
+        busy: HashSet::new(),
+    });
+    let freed = Condvar::new();
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (queue, freed, work) = (&queue, &freed, &work);
+            scope.spawn(move || {
+                while let Some((job, key)) = next_job(queue, freed) {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| work(&job)))
+                        .map_err(|payload| panic_message(payload.as_ref()));
+                    queue.lock().unwrap().busy.remove(&key);
+                    freed.notify_all();
+                    if sender.send((job, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        for (job, result) in receiver {
+            done(job, result);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_of_lowercases_the_host() {
+        assert_eq!(host_of("https://Example.COM/feed.xml"), "example.com");
+        assert_eq!(host_of("not a url"), "not a url");
+    }
+
+    #[test]
+    fn every_job_is_done_once() {
+        let jobs: Vec<u32> = (0..20).collect();
+        let mut results = Vec::new();
+        run_per_host(
+            jobs,
+            4,
+            |job| format!("host{}", job % 3),
+            |job| job * 2,
+            |job, result| results.push((job, result.unwrap())),
+        );
+        results.sort();
+        assert_eq!(
+            results,
+            (0..20).map(|job| (job, job * 2)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn a_panicking_job_is_reported() {
+        let mut results = Vec::new();
+        run_per_host(
+            vec![1, 2, 3],
+            2,
+            |job| job.to_string(),
+            |&job| {
+                if job == 2 {
+                    panic!("job {} failed", job);
+                }
+                job
+            },
+            |job, result| results.push((job, result)),
+        );
+        results.sort();
+        assert_eq!(
+            results,
+            vec![(1, Ok(1)), (2, Err("job 2 failed".to_string())), (3, Ok(3))]
+        );
+    }
+}